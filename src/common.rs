@@ -1,3 +1,5 @@
+use std::fmt::{self, Write};
+
 pub(crate) const HEX_SHIFT: u32 = 4;
 pub(crate) const HEX_MASK: u32 = 0x0F;
 
@@ -33,52 +35,108 @@ pub(crate) const fn char_bucket(c: char) -> usize {
     (c as u32 >> 5) as usize
 }
 
-/// Encodes a single character as a hexadecimal escape sequence and appends it to the output string.
+/// Encodes a single character as a hexadecimal escape sequence and appends it to the output.
 ///
 /// # Parameters
 /// - `escape_char: char`: The prefix character used to denote the beginning of an escape sequence.
 ///   Commonly, this would be a backslash (`'\\'`).
-/// - `output: &mut String`: A mutable reference to the output string where the encoded hex sequence
+/// - `output: &mut W`: The writer (usually a `String`) where the encoded hex sequence
 ///   will be appended.
 /// - `character: char`: The character to be encoded as a hexadecimal byte.
 ///
 /// # Behavior
-/// - The function reserves enough space in the output string to accommodate the escape sequence (`4` characters).
+/// - The escape sequence is `4` characters long and any error from `output` is returned to the caller.
 /// - The escape sequence format is `"{escape_char}xHH"`, where `HH` represents the two-digit hexadecimal
 ///   value of the input character.
 /// - This function uses the constants `HEX`, `HEX_SHIFT`, and `HEX_MASK` to efficiently extract and format
 ///   the hexadecimal digits.
 #[inline]
-pub(crate) fn encode_as_hex_byte(escape_char: char, output: &mut String, character: char) {
-    output.push(escape_char);
-    output.push('x');
-    output.push(HEX[(character as u32 >> HEX_SHIFT) as usize]);
-    output.push(HEX[(character as u32 & HEX_MASK) as usize]);
+pub(crate) fn encode_as_hex_byte<W: Write + ?Sized>(
+    escape_char: char,
+    output: &mut W,
+    character: char,
+) -> fmt::Result {
+    output.write_char(escape_char)?;
+    output.write_char('x')?;
+    output.write_char(HEX[(character as u32 >> HEX_SHIFT) as usize])?;
+    output.write_char(HEX[(character as u32 & HEX_MASK) as usize])
 }
 
-/// Encodes a single character as a Unicode escape sequence and appends it to the output string.
+/// Encodes a single character as a Unicode escape sequence and appends it to the output.
 ///
 /// # Parameters
 /// - `escape_char: char`: The prefix character used to indicate the beginning of the escape sequence.
 ///   Commonly, this would be a backslash (`'\\'`).
-/// - `output: &mut String`: A mutable reference to the output string where the Unicode escape sequence
+/// - `output: &mut W`: The writer (usually a `String`) where the Unicode escape sequence
 ///   will be appended.
 /// - `character: char`: The character to be encoded as a Unicode escape sequence.
 ///
 /// # Behavior
-/// - The function reserves enough space in the output string to accommodate the escape sequence (`6` characters).
 /// - The escape sequence format is `"{escape_char}uHHHH"`, where `HHHH` represents the four-digit
-///   hexadecimal Unicode code point of the input character.
-/// - Hexadecimal digits are efficiently calculated and appended to the output using bitwise operations
-///   and the `HEX` lookup table.
+///   hexadecimal UTF-16 code unit of the input character.
+/// - Characters beyond the Basic Multilingual Plane (above `U+FFFF`) don't fit in a single code unit
+///   and are written as a UTF-16 surrogate pair, e.g. `U+1F600` becomes `"\\ud83d\\ude00"`.
+/// - Any error from `output` is returned to the caller.
+#[inline]
+pub(crate) fn encode_as_unicode<W: Write + ?Sized>(
+    escape_char: char,
+    output: &mut W,
+    character: char,
+) -> fmt::Result {
+    let mut code_units = [0u16; 2];
+    for code_unit in character.encode_utf16(&mut code_units) {
+        encode_as_utf16_code_unit(escape_char, output, *code_unit as u32)?;
+    }
+    Ok(())
+}
+
+/// Writes a single UTF-16 code unit as `"{escape_char}uHHHH"` using the `HEX` lookup table.
+#[inline]
+fn encode_as_utf16_code_unit<W: Write + ?Sized>(
+    escape_char: char,
+    output: &mut W,
+    code_unit: u32,
+) -> fmt::Result {
+    output.write_char(escape_char)?;
+    output.write_char('u')?;
+    output.write_char(HEX[(code_unit >> (3 * HEX_SHIFT)) as usize & HEX_MASK as usize])?;
+    output.write_char(HEX[(code_unit >> (2 * HEX_SHIFT)) as usize & HEX_MASK as usize])?;
+    output.write_char(HEX[(code_unit >> HEX_SHIFT) as usize & HEX_MASK as usize])?;
+    output.write_char(HEX[(code_unit & HEX_MASK) as usize])
+}
+
+/// Encodes a single character as an ES2015 Unicode code point escape and appends it to the output.
+///
+/// # Behavior
+/// - The escape sequence format is `"{escape_char}u{H..}"`, where `H..` is the hexadecimal code point
+///   of the input character without leading zeros, e.g. `U+1F600` becomes `"\\u{1f600}"`.
+/// - Any error from `output` is returned to the caller.
+#[inline]
+pub(crate) fn encode_as_unicode_code_point<W: Write + ?Sized>(
+    escape_char: char,
+    output: &mut W,
+    character: char,
+) -> fmt::Result {
+    output.write_char(escape_char)?;
+    output.write_str("u{")?;
+    encode_as_hex_digits(output, character as u32)?;
+    output.write_char('}')
+}
+
+/// Writes `value` as lower-case hex digits without leading zeros.
 #[inline]
-pub(crate) fn encode_as_unicode(escape_char: char, output: &mut String, character: char) {
-    output.push(escape_char);
-    output.push('u');
-    output.push(HEX[(character as u32 >> (3 * HEX_SHIFT)) as usize & HEX_MASK as usize]);
-    output.push(HEX[(character as u32 >> (2 * HEX_SHIFT)) as usize & HEX_MASK as usize]);
-    output.push(HEX[(character as u32 >> (1 * HEX_SHIFT)) as usize & HEX_MASK as usize]);
-    output.push(HEX[(character as u32 & HEX_MASK) as usize]);
+pub(crate) fn encode_as_hex_digits<W: Write + ?Sized>(output: &mut W, value: u32) -> fmt::Result {
+    let mut shift = 7 * HEX_SHIFT;
+    while shift > 0 && (value >> shift) & HEX_MASK == 0 {
+        shift -= HEX_SHIFT;
+    }
+    loop {
+        output.write_char(HEX[((value >> shift) & HEX_MASK) as usize])?;
+        if shift == 0 {
+            return Ok(());
+        }
+        shift -= HEX_SHIFT;
+    }
 }
 
 pub(crate) fn dump_masks_to_ascii(masks: &[u32; 4]) {
@@ -0,0 +1,248 @@
+use crate::common::{char_bucket, char_mask, encode_as_hex_digits};
+use crate::encoder::Encoder;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CssEncoderMode {
+    /// The content of a string in single or double quotes. Encodes controls, quotes, `\` and
+    /// `<`, `>` and `&`, so the string can't end early or close a `<style>` element.
+    String,
+    /// An identifier such as a class name or a font family, escaped following the `CSS.escape()`
+    /// algorithm: controls and a leading digit are written as hex escapes and every other
+    /// character that isn't a letter, digit, `-` or `_` is escaped with a backslash.
+    ///
+    /// The input is treated as a whole identifier, so a digit at its start is escaped.
+    Identifier,
+    /// The content of an unquoted `url()`. On top of the string characters, whitespace and
+    /// parentheses are encoded.
+    Url,
+}
+
+/// Encodes text for CSS.
+///
+/// Hex escapes are written as `\HH`, followed by a space when the next character is a hex
+/// digit or whitespace and would otherwise be read as part of the escape. A space is also
+/// written at the end of the input, where the next character isn't known. Characters outside
+/// the ASCII range are written as-is, and NUL is replaced with `U+FFFD`, which is what CSS reads
+/// it as anyway.
+pub struct CssEncoder {
+    mode: CssEncoderMode,
+    valid_masks: [u32; 4],
+}
+
+impl CssEncoder {
+    pub fn new(mode: CssEncoderMode) -> Self {
+        let valid_masks = match mode {
+            CssEncoderMode::Identifier => {
+                //  starting from '0' + 10 bits (aka 0-9)
+                let digits = ((1u32 << 10u32) - 1u32) << ('0' as u32 & 31u32);
+                //  starting from 'A' + 26 bits (aka A-Z)
+                let uppercase_a_z = ((1u32 << 26u32) - 1u32) << ('A' as u32 & 31u32);
+                //  starting from 'a' + 26 bits (aka a-z)
+                let lowercase_a_z = ((1u32 << 26u32) - 1u32) << ('a' as u32 & 31u32);
+                [
+                    0,
+                    digits | char_mask('-'),
+                    uppercase_a_z | char_mask('_'),
+                    lowercase_a_z,
+                ]
+            }
+            CssEncoderMode::String | CssEncoderMode::Url => {
+                let mut valid_masks = [0, u32::MAX, u32::MAX, !char_mask('\u{7f}')];
+                let to_be_encoded: &[char] = match mode {
+                    CssEncoderMode::Url => &['"', '\'', '\\', '<', '>', '&', '(', ')', ' '],
+                    _ => &['"', '\'', '\\', '<', '>', '&'],
+                };
+                for c in to_be_encoded {
+                    valid_masks[char_bucket(*c)] &= !char_mask(*c);
+                }
+                valid_masks
+            }
+        };
+        Self { mode, valid_masks }
+    }
+
+    pub fn encode(&self, input: &str) -> String {
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Returns `true` if `c` cannot be written to the output as-is, wherever it appears.
+    #[inline]
+    fn requires_encoding(&self, c: char) -> bool {
+        (c as u32) < 128 && self.valid_masks[char_bucket(c)] & char_mask(c) == 0
+    }
+
+    /// Returns `true` if the character at `index` has to be escaped because of where it is in an
+    /// identifier: a digit at the start or after a leading `-`, or a `-` on its own.
+    #[inline]
+    fn is_identifier_start(&self, input: &str, index: usize, c: char) -> bool {
+        self.mode == CssEncoderMode::Identifier
+            && match index {
+                0 => c.is_ascii_digit() || input == "-",
+                1 => c.is_ascii_digit() && input.starts_with('-'),
+                _ => false,
+            }
+    }
+
+    /// Writes the encoded form of `input` to `output`.
+    ///
+    /// Runs of characters that don't need encoding are written with a single `write_str` call.
+    fn write_encoded<W: Write + ?Sized>(&self, input: &str, output: &mut W) -> fmt::Result {
+        let mut safe_start = 0;
+        let mut chars = input.char_indices().peekable();
+        while let Some((index, c)) = chars.next() {
+            let identifier_start = self.is_identifier_start(input, index, c);
+            if !self.requires_encoding(c) && !identifier_start {
+                continue;
+            }
+            output.write_str(&input[safe_start..index])?;
+            safe_start = index + c.len_utf8();
+            match c {
+                '\0' => output.write_char(char::REPLACEMENT_CHARACTER)?,
+                _ if self.mode == CssEncoderMode::Identifier
+                    && !identifier_start
+                    && !c.is_ascii_control() =>
+                {
+                    output.write_char('\\')?;
+                    output.write_char(c)?;
+                }
+                // `c == '-'` only for a lone `-`, which has to be written as `\-`.
+                '-' => output.write_str("\\-")?,
+                _ => {
+                    output.write_char('\\')?;
+                    encode_as_hex_digits(output, c as u32)?;
+                    let next = chars.peek().map(|&(_, next)| next);
+                    if next.is_none_or(|next| next.is_ascii_hexdigit() || is_css_whitespace(next)) {
+                        output.write_char(' ')?;
+                    }
+                }
+            }
+        }
+        output.write_str(&input[safe_start..])
+    }
+}
+
+/// Returns `true` for the characters CSS reads as whitespace: space, tab, LF, CR and form feed.
+#[inline]
+fn is_css_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\u{c}')
+}
+
+impl Encoder for CssEncoder {
+    fn encode_into(&self, input: &str, output: &mut String) {
+        // Writing to a `String` never fails.
+        let _ = self.write_encoded(input, output);
+    }
+
+    fn encode_fmt(&self, input: &str, output: &mut dyn Write) -> fmt::Result {
+        self.write_encoded(input, output)
+    }
+
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        input
+            .char_indices()
+            .find(|&(index, c)| {
+                self.requires_encoding(c) || self.is_identifier_start(input, index, c)
+            })
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::css_encoder::{CssEncoder, CssEncoderMode};
+    use crate::css_tokenizer::{CssToken, tokenize};
+    use crate::encoder::Encoder;
+
+    fn generic_tests(encoder: &CssEncoder) {
+        assert_eq!("abc-_XYZ", encoder.encode("abc-_XYZ"));
+        assert_eq!("caf\u{e9}\u{1f600}", encoder.encode("caf\u{e9}\u{1f600}"));
+        assert_eq!("\u{fffd}", encoder.encode("\u{0}"));
+        assert_eq!("\\1\\1f\\7f ", encoder.encode("\u{1}\u{1f}\u{7f}"));
+        assert_eq!("\\ax", encoder.encode("\nx"));
+        assert_eq!("\\a\u{e9}", encoder.encode("\n\u{e9}"));
+        assert_eq!("\\a a", encoder.encode("\na"));
+    }
+
+    #[test]
+    fn test_string_encode() {
+        let encoder = CssEncoder::new(CssEncoderMode::String);
+        assert_eq!(
+            "\\3c/style\\3e\\22\\27\\5c ",
+            encoder.encode("</style>\"'\\")
+        );
+        assert_eq!("a\\26 b", encoder.encode("a&b"));
+        assert_eq!("a\\26z", encoder.encode("a&z"));
+        assert_eq!("\\a  ", encoder.encode("\n "));
+        assert_eq!("1 (x) /*", encoder.encode("1 (x) /*"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_identifier_encode() {
+        let encoder = CssEncoder::new(CssEncoderMode::Identifier);
+        assert_eq!("\\31 a2", encoder.encode("1a2"));
+        assert_eq!("\\31x", encoder.encode("1x"));
+        assert_eq!("-\\31 ", encoder.encode("-1"));
+        assert_eq!("\\-", encoder.encode("-"));
+        assert_eq!("--", encoder.encode("--"));
+        assert_eq!("-a1", encoder.encode("-a1"));
+        assert_eq!("a\\ b\\.c\\#d", encoder.encode("a b.c#d"));
+        assert_eq!("\\<\\/style\\>", encoder.encode("</style>"));
+        assert_eq!("\\\"\\'\\\\\\(\\)", encoder.encode("\"'\\()"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_url_encode() {
+        let encoder = CssEncoder::new(CssEncoderMode::Url);
+        assert_eq!(
+            "https://example.com/a\\20 b\\28 1\\29?q=1\\26x",
+            encoder.encode("https://example.com/a b(1)?q=1&x")
+        );
+        assert_eq!("\\22\\27\\5c\\3c\\3e ", encoder.encode("\"'\\<>"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "1-\u{1}a\n f\"'\\<>&()/* \u{e9}\u{1f600}\u{7f}9";
+        let encode = |mode| CssEncoder::new(mode).encode(input);
+        assert_eq!(
+            vec![CssToken::String(input.into())],
+            tokenize(&format!("\"{}\"", encode(CssEncoderMode::String)))
+        );
+        assert_eq!(
+            vec![CssToken::String(input.into())],
+            tokenize(&format!("'{}'", encode(CssEncoderMode::String)))
+        );
+        assert_eq!(
+            vec![CssToken::Ident(input.into())],
+            tokenize(&encode(CssEncoderMode::Identifier))
+        );
+        assert_eq!(
+            vec![CssToken::Url(input.into())],
+            tokenize(&format!("url({})", encode(CssEncoderMode::Url)))
+        );
+        for ident in ["-", "-1", "--", "1", "-a"] {
+            let encoded = CssEncoder::new(CssEncoderMode::Identifier).encode(ident);
+            assert_eq!(vec![CssToken::Ident(ident.into())], tokenize(&encoded));
+        }
+    }
+
+    #[test]
+    fn test_cow() {
+        let encoder = CssEncoder::new(CssEncoderMode::Identifier);
+        assert!(!encoder.needs_encoding("font-family_\u{e9}"));
+        assert_eq!(Some(0), encoder.first_unsafe_index("1a"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("-1"));
+        assert_eq!(Some(0), encoder.first_unsafe_index("-"));
+        assert_eq!(Some(3), encoder.first_unsafe_index("abc def"));
+        assert_eq!("-\\31 ", encoder.encode_cow("-1"));
+        assert!(!CssEncoder::new(CssEncoderMode::String).needs_encoding("1 (x)"));
+        assert_eq!(
+            Some(1),
+            CssEncoder::new(CssEncoderMode::Url).first_unsafe_index("a(")
+        );
+    }
+}
@@ -0,0 +1,606 @@
+use crate::css_encoder::{CssEncoder, CssEncoderMode};
+use crate::css_tokenizer::{CssToken, tokenize};
+use crate::encoder::Encoder;
+use crate::uri_validator::UriValidator;
+use std::collections::HashSet;
+
+/// Which properties, functions and URL schemes a [`CssSanitizer`] lets through.
+///
+/// Property and function names are matched case-insensitively. `url()` is only allowed if
+/// `url` is one of the allowed functions, and then only for relative URLs and URLs with an
+/// allowed scheme.
+#[derive(Debug, Clone, Default)]
+pub struct CssPolicy {
+    properties: HashSet<String>,
+    functions: HashSet<String>,
+    url_schemes: HashSet<String>,
+}
+
+impl CssPolicy {
+    /// A policy that removes every declaration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy for inline styles of rich text: colors, fonts, text layout, margins, padding,
+    /// borders and sizes, without any URLs or positioning.
+    pub fn basic() -> Self {
+        Self::new()
+            .with_allowed_properties(&[
+                "background-color",
+                "border",
+                "border-bottom",
+                "border-collapse",
+                "border-color",
+                "border-left",
+                "border-radius",
+                "border-right",
+                "border-style",
+                "border-top",
+                "border-width",
+                "color",
+                "direction",
+                "display",
+                "font",
+                "font-family",
+                "font-size",
+                "font-style",
+                "font-variant",
+                "font-weight",
+                "height",
+                "letter-spacing",
+                "line-height",
+                "list-style-type",
+                "margin",
+                "margin-bottom",
+                "margin-left",
+                "margin-right",
+                "margin-top",
+                "max-height",
+                "max-width",
+                "min-height",
+                "min-width",
+                "padding",
+                "padding-bottom",
+                "padding-left",
+                "padding-right",
+                "padding-top",
+                "text-align",
+                "text-decoration",
+                "text-indent",
+                "text-transform",
+                "vertical-align",
+                "white-space",
+                "width",
+                "word-spacing",
+            ])
+            .with_allowed_functions(&["hsl", "hsla", "rgb", "rgba"])
+    }
+
+    pub fn with_allowed_properties(mut self, properties: &[&str]) -> Self {
+        self.properties
+            .extend(properties.iter().map(|p| p.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows these functions, such as `rgb`, in property values.
+    pub fn with_allowed_functions(mut self, functions: &[&str]) -> Self {
+        self.functions
+            .extend(functions.iter().map(|f| f.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows absolute URLs with these schemes in `url()`. Relative URLs are always allowed.
+    pub fn with_allowed_url_schemes(mut self, schemes: &[&str]) -> Self {
+        self.url_schemes
+            .extend(schemes.iter().map(|s| s.to_ascii_lowercase()));
+        self
+    }
+}
+
+/// Cleans untrusted CSS from `style` attributes and `<style>` elements down to what a
+/// [`CssPolicy`] allows.
+///
+/// The input is tokenized following CSS Syntax Level 3, so comments and escapes can't hide
+/// anything from the sanitizer: `e\78pression(` is the function `expression`. Declarations of
+/// properties that aren't allowed are removed, such as `behavior` and `-moz-binding`, and so
+/// are declarations whose value contains anything but identifiers, numbers, colors, strings,
+/// allowed functions and a few separators. At-rules such as `@import` are always removed.
+///
+/// The output is rebuilt from the tokens that survive, with identifiers, strings and URLs
+/// escaped again by a [`CssEncoder`], so it can't end a `<style>` element early.
+///
+/// # Example
+/// ```
+/// use tiny_clean::css_sanitizer::{CssPolicy, CssSanitizer};
+///
+/// let sanitizer = CssSanitizer::new(CssPolicy::basic());
+/// assert_eq!(
+///     "color: red; margin: 0 auto",
+///     sanitizer.sanitize_declarations("color:red; width: expression(alert(1)); margin:0 auto")
+/// );
+/// ```
+pub struct CssSanitizer {
+    policy: CssPolicy,
+    identifier: CssEncoder,
+    string: CssEncoder,
+    url: CssEncoder,
+    urls: UriValidator,
+}
+
+impl CssSanitizer {
+    pub fn new(policy: CssPolicy) -> Self {
+        Self {
+            urls: UriValidator::from_schemes(&policy.url_schemes),
+            policy,
+            identifier: CssEncoder::new(CssEncoderMode::Identifier),
+            string: CssEncoder::new(CssEncoderMode::String),
+            url: CssEncoder::new(CssEncoderMode::Url),
+        }
+    }
+
+    pub fn policy(&self) -> &CssPolicy {
+        &self.policy
+    }
+
+    /// Sanitizes a list of declarations, such as the value of a `style` attribute.
+    pub fn sanitize_declarations(&self, input: &str) -> String {
+        self.declarations(&tokenize(input))
+    }
+
+    /// Sanitizes a style sheet, such as the content of a `<style>` element. Rules with
+    /// selectors made of anything but type, class, id, attribute and pseudo-class selectors and
+    /// combinators are removed, as are rules without any allowed declaration.
+    pub fn sanitize_stylesheet(&self, input: &str) -> String {
+        let tokens = tokenize(input);
+        let mut output = String::new();
+        let mut rest = &tokens[..];
+        while let [first, remaining @ ..] = rest {
+            match first {
+                CssToken::Whitespace | CssToken::Cdo | CssToken::Cdc => rest = remaining,
+                CssToken::AtKeyword(_) => {
+                    let end = find_top_level(remaining, |t| {
+                        matches!(t, CssToken::Semicolon | CssToken::OpenCurly)
+                    });
+                    rest = match end {
+                        Some(end) if remaining[end] == CssToken::OpenCurly => {
+                            skip_block(&remaining[end + 1..])
+                        }
+                        Some(end) => &remaining[end + 1..],
+                        None => &[],
+                    };
+                }
+                _ => {
+                    // A rule without a block is dropped together with the rest of the input.
+                    let Some(open) = find_top_level(rest, |t| *t == CssToken::OpenCurly) else {
+                        break;
+                    };
+                    let block = &rest[open + 1..];
+                    let close = find_top_level(block, |t| *t == CssToken::CloseCurly);
+                    let declarations = self.declarations(&block[..close.unwrap_or(block.len())]);
+                    if let Some(selector) = self.serialize_selector(trim_whitespace(&rest[..open]))
+                        && !declarations.is_empty()
+                    {
+                        if !output.is_empty() {
+                            output.push('\n');
+                        }
+                        output.push_str(&selector);
+                        output.push_str(" { ");
+                        output.push_str(&declarations);
+                        output.push_str(" }");
+                    }
+                    rest = skip_block(block);
+                }
+            }
+        }
+        output
+    }
+
+    /// Sanitizes the declarations in `tokens`, separated by `;`.
+    fn declarations(&self, mut tokens: &[CssToken]) -> String {
+        let mut output = String::new();
+        while !tokens.is_empty() {
+            let end = find_top_level(tokens, |t| *t == CssToken::Semicolon).unwrap_or(tokens.len());
+            if let Some(declaration) = self.declaration(&tokens[..end]) {
+                if !output.is_empty() {
+                    output.push_str("; ");
+                }
+                output.push_str(&declaration);
+            }
+            tokens = &tokens[(end + 1).min(tokens.len())..];
+        }
+        output
+    }
+
+    /// Returns the serialized declaration, or `None` if it's malformed or not allowed.
+    fn declaration(&self, tokens: &[CssToken]) -> Option<String> {
+        let [CssToken::Ident(name), rest @ ..] = trim_whitespace(tokens) else {
+            return None;
+        };
+        let [CssToken::Colon, value @ ..] = trim_whitespace(rest) else {
+            return None;
+        };
+        let name = name.to_ascii_lowercase();
+        if !self.policy.properties.contains(&name) {
+            return None;
+        }
+        let (value, important) = split_important(trim_whitespace(value));
+        if value.is_empty() {
+            return None;
+        }
+        let mut output = String::new();
+        self.identifier.encode_into(&name, &mut output);
+        output.push_str(": ");
+        output.push_str(&self.serialize_value(value)?);
+        if important {
+            output.push_str(" !important");
+        }
+        Some(output)
+    }
+
+    /// Serializes a property value, or returns `None` if it contains anything not allowed.
+    fn serialize_value(&self, tokens: &[CssToken]) -> Option<String> {
+        let mut output = String::new();
+        // How many allowed functions are open.
+        let mut depth = 0;
+        let mut previous: Option<&CssToken> = None;
+        let mut index = 0;
+        while let Some(token) = tokens.get(index) {
+            index += 1;
+            if *token == CssToken::Whitespace && previous == Some(&CssToken::Whitespace) {
+                continue;
+            }
+            if previous.is_some_and(|previous| needs_separator(previous, token)) {
+                output.push_str("/**/");
+            }
+            match token {
+                CssToken::Function(name) if name.eq_ignore_ascii_case("url") => {
+                    let (url, len) = url_argument(&tokens[index..])?;
+                    index += len;
+                    self.write_url(url, &mut output)?;
+                }
+                CssToken::Url(url) => self.write_url(url, &mut output)?,
+                CssToken::Function(name)
+                    if self.policy.functions.contains(&name.to_ascii_lowercase()) =>
+                {
+                    self.identifier.encode_into(name, &mut output);
+                    output.push('(');
+                    depth += 1;
+                }
+                CssToken::CloseParen if depth > 0 => {
+                    output.push(')');
+                    depth -= 1;
+                }
+                CssToken::Delim(c @ ('/' | '*' | '+' | '-' | '.')) => output.push(*c),
+                CssToken::Comma => output.push(','),
+                _ => self.write_common_token(token, &mut output)?,
+            }
+            previous = Some(token);
+        }
+        (depth == 0).then_some(output)
+    }
+
+    fn write_url(&self, url: &str, output: &mut String) -> Option<()> {
+        if !self.policy.functions.contains("url") || !self.urls.is_allowed(url) {
+            return None;
+        }
+        output.push_str("url(");
+        self.url.encode_into(url, output);
+        output.push(')');
+        Some(())
+    }
+
+    /// Serializes the tokens that are allowed both in property values and in selectors.
+    fn write_common_token(&self, token: &CssToken, output: &mut String) -> Option<()> {
+        match token {
+            CssToken::Whitespace => output.push(' '),
+            CssToken::Ident(ident) => self.identifier.encode_into(ident, output),
+            CssToken::Hash(name) => {
+                // Unlike an identifier, a hash name may start with a digit.
+                let start = name
+                    .find(|c: char| !(c.is_ascii_digit() || c == '-'))
+                    .unwrap_or(name.len());
+                output.push('#');
+                output.push_str(&name[..start]);
+                self.identifier.encode_into(&name[start..], output);
+            }
+            CssToken::String(value) => {
+                output.push('"');
+                self.string.encode_into(value, output);
+                output.push('"');
+            }
+            CssToken::Number(number) => output.push_str(number),
+            CssToken::Percentage(number) => {
+                output.push_str(number);
+                output.push('%');
+            }
+            CssToken::Dimension(number, unit) => {
+                output.push_str(number);
+                let unit = self.identifier.encode_cow(unit);
+                // A unit such as `e3` would be read back as an exponent.
+                let bytes = unit.as_bytes();
+                let exponent = matches!(bytes, [b'e' | b'E', b'0'..=b'9', ..])
+                    || matches!(bytes, [b'e' | b'E', b'-', b'0'..=b'9', ..]);
+                if exponent {
+                    output.push_str(if bytes[0] == b'e' { "\\65 " } else { "\\45 " });
+                    output.push_str(&unit[1..]);
+                } else {
+                    output.push_str(&unit);
+                }
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Serializes a selector, or returns `None` if it contains anything but simple selectors,
+    /// pseudo-classes without arguments and combinators.
+    fn serialize_selector(&self, tokens: &[CssToken]) -> Option<String> {
+        let mut output = String::new();
+        let mut in_attribute = false;
+        let mut previous: Option<&CssToken> = None;
+        for token in tokens {
+            if *token == CssToken::Whitespace && previous == Some(&CssToken::Whitespace) {
+                continue;
+            }
+            if previous.is_some_and(|previous| needs_separator(previous, token)) {
+                output.push_str("/**/");
+            }
+            match token {
+                CssToken::Colon => output.push(':'),
+                CssToken::Comma => output.push(','),
+                CssToken::Delim(c @ ('.' | '*' | '>' | '+' | '~')) => output.push(*c),
+                CssToken::OpenSquare if !in_attribute => {
+                    output.push('[');
+                    in_attribute = true;
+                }
+                CssToken::CloseSquare if in_attribute => {
+                    output.push(']');
+                    in_attribute = false;
+                }
+                CssToken::Delim(c @ ('=' | '^' | '$' | '|')) if in_attribute => output.push(*c),
+                CssToken::Number(_) | CssToken::Percentage(_) | CssToken::Dimension(..) => {
+                    return None;
+                }
+                _ => self.write_common_token(token, &mut output)?,
+            }
+            previous = Some(token);
+        }
+        (!in_attribute && !output.is_empty()).then_some(output)
+    }
+}
+
+/// Returns the URL of a `url(` function with a string argument, and how many tokens it takes
+/// up after the function token.
+fn url_argument(tokens: &[CssToken]) -> Option<(&str, usize)> {
+    let mut iter = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| **t != CssToken::Whitespace);
+    match (iter.next(), iter.next()) {
+        (Some((_, CssToken::String(url))), Some((end, CssToken::CloseParen))) => {
+            Some((url, end + 1))
+        }
+        _ => None,
+    }
+}
+
+/// Finds the first token matching `predicate` that isn't nested in a block or a function.
+fn find_top_level(tokens: &[CssToken], predicate: impl Fn(&CssToken) -> bool) -> Option<usize> {
+    let mut closers = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if closers.is_empty() && predicate(token) {
+            return Some(index);
+        }
+        match token {
+            CssToken::Function(_) | CssToken::OpenParen => closers.push(CssToken::CloseParen),
+            CssToken::OpenSquare => closers.push(CssToken::CloseSquare),
+            CssToken::OpenCurly => closers.push(CssToken::CloseCurly),
+            CssToken::CloseParen | CssToken::CloseSquare | CssToken::CloseCurly
+                if closers.last() == Some(token) =>
+            {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the tokens after the `}` that closes a block, given the tokens after its `{`.
+fn skip_block(block: &[CssToken]) -> &[CssToken] {
+    match find_top_level(block, |t| *t == CssToken::CloseCurly) {
+        Some(end) => &block[end + 1..],
+        None => &[],
+    }
+}
+
+fn trim_whitespace(mut tokens: &[CssToken]) -> &[CssToken] {
+    while let [CssToken::Whitespace, rest @ ..] = tokens {
+        tokens = rest;
+    }
+    while let [rest @ .., CssToken::Whitespace] = tokens {
+        tokens = rest;
+    }
+    tokens
+}
+
+/// Splits a trailing `!important` off a property value.
+fn split_important(tokens: &[CssToken]) -> (&[CssToken], bool) {
+    if let [rest @ .., CssToken::Ident(ident)] = tokens
+        && ident.eq_ignore_ascii_case("important")
+        && let [value @ .., CssToken::Delim('!')] = trim_whitespace(rest)
+    {
+        return (trim_whitespace(value), true);
+    }
+    (tokens, false)
+}
+
+/// Returns `true` if `previous` and `next` would be read back as a different token when written
+/// next to each other, following the serialization rules of CSS Syntax Level 3.
+fn needs_separator(previous: &CssToken, next: &CssToken) -> bool {
+    let starts_number = matches!(
+        next,
+        CssToken::Number(_) | CssToken::Percentage(_) | CssToken::Dimension(..)
+    );
+    let starts_word = starts_number
+        || matches!(
+            next,
+            CssToken::Ident(_) | CssToken::Function(_) | CssToken::Url(_)
+        );
+    match previous {
+        CssToken::Ident(_) | CssToken::Hash(_) | CssToken::Dimension(..) | CssToken::Delim('-') => {
+            starts_word || *next == CssToken::Delim('-')
+        }
+        CssToken::Number(_) => starts_word,
+        CssToken::Delim('.' | '+') => starts_number,
+        CssToken::Delim('/') => *next == CssToken::Delim('*'),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::css_sanitizer::{CssPolicy, CssSanitizer};
+
+    fn basic() -> CssSanitizer {
+        CssSanitizer::new(CssPolicy::basic())
+    }
+
+    #[test]
+    fn test_declarations() {
+        let sanitizer = basic();
+        assert_eq!(
+            "color: red; font-weight: bold; margin: 0 auto",
+            sanitizer.sanitize_declarations("color:red;font-weight : bold ; margin:0  auto;")
+        );
+        assert_eq!(
+            "color: RGB(0,128,255) !important",
+            sanitizer.sanitize_declarations("COLOR: RGB(0,128,255)!IMPORTANT")
+        );
+        assert_eq!(
+            "font: italic 12px/1.5 \"Open Sans\", serif",
+            sanitizer.sanitize_declarations("font: italic 12px/1.5 'Open Sans', serif")
+        );
+        assert_eq!(
+            "background-color: #fff; width: -1.5e3px",
+            sanitizer.sanitize_declarations("background-color:#fff;width:-1.5e3px")
+        );
+        assert_eq!(
+            "",
+            sanitizer.sanitize_declarations("color:; :red; color red")
+        );
+        assert_eq!(
+            "",
+            CssSanitizer::new(CssPolicy::new()).sanitize_declarations("color: red")
+        );
+    }
+
+    #[test]
+    fn test_exploits() {
+        let sanitizer = CssSanitizer::new(
+            CssPolicy::basic()
+                .with_allowed_properties(&["background-image"])
+                .with_allowed_functions(&["url"])
+                .with_allowed_url_schemes(&["https"]),
+        );
+        for input in [
+            "width: expression(alert(1))",
+            "width: e\\78pression(alert(1))",
+            "width: expr/**/ession(alert(1))",
+            "behavior: url(x.htc)",
+            "-moz-binding: url(x.xml#xss)",
+            "background-image: url(javascript:alert(1))",
+            "background-image: url( 'java\\9script:alert(1)' )",
+            "background-image: url(\"javascript:alert(1)\")",
+            "background-image: url(data:image/svg+xml,x)",
+            "background-image: url(a b)",
+            "color: red; } body { color: red",
+            "color: red @import 'x'",
+            "color: rgb(0,0,0",
+            "color: <script>",
+            "color: \"unterminated\n",
+        ] {
+            let output = sanitizer.sanitize_declarations(input);
+            assert!(
+                output.is_empty() || output == "color: red",
+                "{input:?} -> {output:?}"
+            );
+        }
+        assert_eq!(
+            "background-image: url(https://example.com/a.png); color: red",
+            sanitizer.sanitize_declarations(
+                "background-image:url(https://example.com/a.png);x:expression(1);color:red"
+            )
+        );
+        assert_eq!(
+            "background-image: url(/a\\29.png)",
+            sanitizer.sanitize_declarations("background-image:url('/a).png')")
+        );
+        assert_eq!(
+            "",
+            basic().sanitize_declarations("background-image: url(/a.png)")
+        );
+    }
+
+    #[test]
+    fn test_escaping() {
+        let sanitizer = basic();
+        assert_eq!(
+            "font-family: \"\\3c/style\\3e\\22\\27\\5c \"",
+            sanitizer.sanitize_declarations("font-family: '</style>\"\\'\\\\'")
+        );
+        assert_eq!(
+            "font-family: a\\<b, \\31 a, -\\32 , \\-, caf\u{e9}",
+            sanitizer.sanitize_declarations("font-family: a\\<b, \\31 a, -\\32, \\-, caf\\e9")
+        );
+        assert_eq!(
+            "font-family: a/**/b",
+            sanitizer.sanitize_declarations("font-family: a/**/b")
+        );
+        assert_eq!(
+            "width: 1\\65 3",
+            sanitizer.sanitize_declarations("width: 1\\65 3")
+        );
+        assert_eq!(
+            "width: -/**/a",
+            sanitizer.sanitize_declarations("width: -/**/a")
+        );
+    }
+
+    #[test]
+    fn test_stylesheet() {
+        let sanitizer = basic();
+        assert_eq!(
+            "p.note, h1 > a:hover { color: red }\n#main [title~=\"x\"] { margin: 0 }",
+            sanitizer.sanitize_stylesheet(
+                "<!-- p.note, h1 > a:hover { color: red; position: fixed }\n\
+                 #main [title~='x']{margin:0} -->"
+            )
+        );
+        assert_eq!(
+            "b { color: red }",
+            sanitizer.sanitize_stylesheet(
+                "@import url(evil.css); @media screen { a { color: red } } b { color: red }"
+            )
+        );
+        assert_eq!(
+            "b { color: red }",
+            sanitizer.sanitize_stylesheet(
+                "a { behavior: url(x.htc) } a:not(.x) { color: red } b { color: red }"
+            )
+        );
+        assert_eq!(
+            "b { color: red }",
+            sanitizer.sanitize_stylesheet("a { color: (}) } b { color: red } c")
+        );
+        assert_eq!(
+            "a\\<b { color: red }",
+            sanitizer.sanitize_stylesheet("a\\<b { color: red }")
+        );
+        assert_eq!(
+            "a { color: red }",
+            sanitizer.sanitize_stylesheet("a { color: red")
+        );
+    }
+}
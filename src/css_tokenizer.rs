@@ -0,0 +1,471 @@
+//! A tokenizer implementing the tokenization algorithm of CSS Syntax Module Level 3. Comments
+//! are skipped and escapes are resolved, so every token carries the value a browser would see.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CssToken {
+    Ident(String),
+    /// A function name, including `url` when its argument is a quoted string.
+    Function(String),
+    AtKeyword(String),
+    Hash(String),
+    String(String),
+    BadString,
+    Url(String),
+    BadUrl,
+    Delim(char),
+    /// The number as written in the input, e.g. `-1.5e3`.
+    Number(String),
+    Percentage(String),
+    Dimension(String, String),
+    Whitespace,
+    Cdo,
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+}
+
+/// Splits `input` into tokens.
+pub(crate) fn tokenize(input: &str) -> Vec<CssToken> {
+    let mut tokenizer = Tokenizer {
+        chars: preprocess(input),
+        pos: 0,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = tokenizer.next_token() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+/// Normalizes newlines to LF and replaces NUL with `U+FFFD`.
+fn preprocess(input: &str) -> Vec<char> {
+    let mut chars = Vec::with_capacity(input.len());
+    let mut iter = input.chars().peekable();
+    while let Some(c) = iter.next() {
+        match c {
+            '\r' => {
+                iter.next_if_eq(&'\n');
+                chars.push('\n');
+            }
+            '\u{c}' => chars.push('\n'),
+            '\0' => chars.push(char::REPLACEMENT_CHARACTER),
+            _ => chars.push(c),
+        }
+    }
+    chars
+}
+
+#[inline]
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n')
+}
+
+#[inline]
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c as u32 >= 0x80
+}
+
+#[inline]
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+#[inline]
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\u{8}' | '\u{b}' | '\u{e}'..='\u{1f}' | '\u{7f}')
+}
+
+fn is_valid_escape(first: Option<char>, second: Option<char>) -> bool {
+    first == Some('\\') && second != Some('\n')
+}
+
+fn starts_ident(first: Option<char>, second: Option<char>, third: Option<char>) -> bool {
+    match first {
+        Some('-') => {
+            second.is_some_and(|c| is_name_start(c) || c == '-') || is_valid_escape(second, third)
+        }
+        Some('\\') => is_valid_escape(first, second),
+        Some(c) => is_name_start(c),
+        None => false,
+    }
+}
+
+fn starts_number(first: Option<char>, second: Option<char>, third: Option<char>) -> bool {
+    let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+    match first {
+        Some('+' | '-') => is_digit(second) || (second == Some('.') && is_digit(third)),
+        Some('.') => is_digit(second),
+        c => is_digit(c),
+    }
+}
+
+struct Tokenizer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Tokenizer {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek(0);
+        self.pos += 1;
+        c
+    }
+
+    fn next_token(&mut self) -> Option<CssToken> {
+        self.skip_comments();
+        let c = self.bump()?;
+        Some(match c {
+            _ if is_whitespace(c) => {
+                while self.peek(0).is_some_and(is_whitespace) {
+                    self.pos += 1;
+                }
+                CssToken::Whitespace
+            }
+            '"' | '\'' => self.string(c),
+            '#' => {
+                if self.peek(0).is_some_and(is_name_char)
+                    || is_valid_escape(self.peek(0), self.peek(1))
+                {
+                    CssToken::Hash(self.name())
+                } else {
+                    CssToken::Delim('#')
+                }
+            }
+            '(' => CssToken::OpenParen,
+            ')' => CssToken::CloseParen,
+            '[' => CssToken::OpenSquare,
+            ']' => CssToken::CloseSquare,
+            '{' => CssToken::OpenCurly,
+            '}' => CssToken::CloseCurly,
+            ',' => CssToken::Comma,
+            ':' => CssToken::Colon,
+            ';' => CssToken::Semicolon,
+            '+' | '.' if starts_number(Some(c), self.peek(0), self.peek(1)) => {
+                self.pos -= 1;
+                self.numeric()
+            }
+            '-' if starts_number(Some(c), self.peek(0), self.peek(1)) => {
+                self.pos -= 1;
+                self.numeric()
+            }
+            '-' if self.peek(0) == Some('-') && self.peek(1) == Some('>') => {
+                self.pos += 2;
+                CssToken::Cdc
+            }
+            '-' if starts_ident(Some(c), self.peek(0), self.peek(1)) => {
+                self.pos -= 1;
+                self.ident_like()
+            }
+            '<' if self.peek(0) == Some('!')
+                && self.peek(1) == Some('-')
+                && self.peek(2) == Some('-') =>
+            {
+                self.pos += 3;
+                CssToken::Cdo
+            }
+            '@' if starts_ident(self.peek(0), self.peek(1), self.peek(2)) => {
+                CssToken::AtKeyword(self.name())
+            }
+            '\\' if is_valid_escape(Some(c), self.peek(0)) => {
+                self.pos -= 1;
+                self.ident_like()
+            }
+            _ if c.is_ascii_digit() => {
+                self.pos -= 1;
+                self.numeric()
+            }
+            _ if is_name_start(c) => {
+                self.pos -= 1;
+                self.ident_like()
+            }
+            _ => CssToken::Delim(c),
+        })
+    }
+
+    fn skip_comments(&mut self) {
+        while self.peek(0) == Some('/') && self.peek(1) == Some('*') {
+            self.pos += 2;
+            while self.pos < self.chars.len()
+                && !(self.peek(0) == Some('*') && self.peek(1) == Some('/'))
+            {
+                self.pos += 1;
+            }
+            self.pos = (self.pos + 2).min(self.chars.len());
+        }
+    }
+
+    /// Consumes an escape; the backslash has been consumed already.
+    fn escape(&mut self) -> char {
+        let Some(c) = self.bump() else {
+            return char::REPLACEMENT_CHARACTER;
+        };
+        let Some(digit) = c.to_digit(16) else {
+            return c;
+        };
+        let mut value = digit;
+        for _ in 0..5 {
+            match self.peek(0).and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    value = value * 16 + digit;
+                    self.pos += 1;
+                }
+                None => break,
+            }
+        }
+        if self.peek(0).is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        match value {
+            0 => char::REPLACEMENT_CHARACTER,
+            _ => char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name_char(c) => {
+                    name.push(c);
+                    self.pos += 1;
+                }
+                c if is_valid_escape(c, self.peek(1)) => {
+                    self.pos += 1;
+                    name.push(self.escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    fn number(&mut self) -> String {
+        let start = self.pos;
+        let digits = |tokenizer: &mut Self| {
+            while tokenizer.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+                tokenizer.pos += 1;
+            }
+        };
+        if matches!(self.peek(0), Some('+' | '-')) {
+            self.pos += 1;
+        }
+        digits(self);
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            digits(self);
+        }
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+            if digit(self.peek(1)) {
+                self.pos += 1;
+                digits(self);
+            } else if matches!(self.peek(1), Some('+' | '-')) && digit(self.peek(2)) {
+                self.pos += 2;
+                digits(self);
+            }
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn numeric(&mut self) -> CssToken {
+        let number = self.number();
+        if starts_ident(self.peek(0), self.peek(1), self.peek(2)) {
+            CssToken::Dimension(number, self.name())
+        } else if self.peek(0) == Some('%') {
+            self.pos += 1;
+            CssToken::Percentage(number)
+        } else {
+            CssToken::Number(number)
+        }
+    }
+
+    fn ident_like(&mut self) -> CssToken {
+        let name = self.name();
+        if self.peek(0) != Some('(') {
+            return CssToken::Ident(name);
+        }
+        self.pos += 1;
+        if !name.eq_ignore_ascii_case("url") {
+            return CssToken::Function(name);
+        }
+        while self.peek(0).is_some_and(is_whitespace) && self.peek(1).is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        let next = match self.peek(0) {
+            Some(c) if is_whitespace(c) => self.peek(1),
+            c => c,
+        };
+        if matches!(next, Some('"' | '\'')) {
+            CssToken::Function(name)
+        } else {
+            self.url()
+        }
+    }
+
+    fn string(&mut self, ending: char) -> CssToken {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => return CssToken::String(value),
+                Some(c) if c == ending => {
+                    self.pos += 1;
+                    return CssToken::String(value);
+                }
+                Some('\n') => return CssToken::BadString,
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek(0) {
+                        None => {}
+                        Some('\n') => self.pos += 1,
+                        Some(_) => value.push(self.escape()),
+                    }
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Consumes an unquoted `url(...)`, after the opening parenthesis.
+    fn url(&mut self) -> CssToken {
+        let mut value = String::new();
+        while self.peek(0).is_some_and(is_whitespace) {
+            self.pos += 1;
+        }
+        loop {
+            match self.bump() {
+                None | Some(')') => return CssToken::Url(value),
+                Some(c) if is_whitespace(c) => {
+                    while self.peek(0).is_some_and(is_whitespace) {
+                        self.pos += 1;
+                    }
+                    return match self.peek(0) {
+                        None => CssToken::Url(value),
+                        Some(')') => {
+                            self.pos += 1;
+                            CssToken::Url(value)
+                        }
+                        Some(_) => self.bad_url(),
+                    };
+                }
+                Some('"' | '\'' | '(') => return self.bad_url(),
+                Some(c) if is_non_printable(c) => return self.bad_url(),
+                Some('\\') if is_valid_escape(Some('\\'), self.peek(0)) => {
+                    value.push(self.escape())
+                }
+                Some('\\') => return self.bad_url(),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    /// Skips the rest of a malformed `url(...)`.
+    fn bad_url(&mut self) -> CssToken {
+        loop {
+            match self.bump() {
+                None | Some(')') => return CssToken::BadUrl,
+                Some('\\') if is_valid_escape(Some('\\'), self.peek(0)) => {
+                    self.escape();
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::css_tokenizer::CssToken::*;
+    use crate::css_tokenizer::tokenize;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            vec![
+                Ident("color".into()),
+                Colon,
+                Whitespace,
+                Hash("f00".into()),
+                Semicolon,
+                Ident("margin".into()),
+                Colon,
+                Number("-1.5e3".into()),
+                Whitespace,
+                Whitespace,
+                Dimension("2".into(), "px".into()),
+                Whitespace,
+                Percentage("+.5".into()),
+            ],
+            tokenize("color: #f00;margin:-1.5e3 /* comment */ 2px +.5%")
+        );
+        assert_eq!(
+            vec![
+                Function("rgb".into()),
+                Number("1".into()),
+                Comma,
+                CloseParen,
+                AtKeyword("import".into()),
+                Delim('!'),
+                Cdo,
+                Cdc,
+                Delim('<'),
+            ],
+            tokenize("rgb(1,)@import!<!---->/**/<")
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(vec![Ident("expression".into())], tokenize("e\\78pression"));
+        assert_eq!(
+            vec![Ident("expression".into())],
+            tokenize("e\\000078 pression")
+        );
+        assert_eq!(vec![Ident("a\u{fffd}b".into())], tokenize("a\\0 b"));
+        assert_eq!(vec![Ident("a(b".into())], tokenize("a\\(b"));
+        assert_eq!(vec![Ident("-\u{e9}".into())], tokenize("-\\e9"));
+        assert_eq!(vec![Delim('\\'), Whitespace], tokenize("\\\n"));
+        assert_eq!(vec![String("a'b\u{e9}c".into())], tokenize("'a\\'b\\e9 c'"));
+        assert_eq!(vec![String("ab".into())], tokenize("\"a\\\nb\""));
+        assert_eq!(
+            vec![BadString, Whitespace, Ident("x".into())],
+            tokenize("\"a\nx")
+        );
+    }
+
+    #[test]
+    fn test_urls() {
+        assert_eq!(
+            vec![Url("javascript:alert(1".into()), CloseParen],
+            tokenize("url( javascript:alert\\(1 ))")
+        );
+        assert_eq!(
+            vec![
+                Function("URL".into()),
+                Whitespace,
+                String("a b".into()),
+                CloseParen
+            ],
+            tokenize("URL(  'a b')")
+        );
+        assert_eq!(vec![BadUrl, Ident("x".into())], tokenize("url(a b)x"));
+        assert_eq!(vec![BadUrl], tokenize("url(a\"b)"));
+        assert_eq!(vec![Url("a".into())], tokenize("url(a"));
+        assert_eq!(
+            vec![Function("expression".into()), Ident("alert".into())],
+            tokenize("expression(alert")
+        );
+    }
+}
@@ -0,0 +1,358 @@
+use std::borrow::Cow;
+use std::fmt;
+
+/// Common interface implemented by every encoder in this crate.
+///
+/// The trait is object safe, so callers that pick an output context at runtime can hold a
+/// `Box<dyn Encoder>` (or `&dyn Encoder`) and treat all encoders uniformly.
+///
+/// # Example
+/// ```
+/// use tiny_clean::encoder::Encoder;
+/// use tiny_clean::java_script_encoder::{JavaScriptEncoder, JavaScriptEncoderMode};
+/// use tiny_clean::xml_encoder::{XmlEncoder, XmlEncoderMode};
+///
+/// let encoders: Vec<Box<dyn Encoder>> = vec![
+///     Box::new(XmlEncoder::new(XmlEncoderMode::Content)),
+///     Box::new(JavaScriptEncoder::new(JavaScriptEncoderMode::Block, true)),
+/// ];
+/// let encoded: Vec<String> = encoders.iter().map(|e| e.encode("a<b")).collect();
+/// assert_eq!(vec!["a&lt;b", "a<b"], encoded);
+/// ```
+pub trait Encoder {
+    /// Appends the encoded form of `input` to the end of `output`.
+    fn encode_into(&self, input: &str, output: &mut String);
+
+    /// Writes the encoded form of `input` to any [`fmt::Write`] implementation, such as a
+    /// `fmt::Formatter`, without building an intermediate `String`.
+    ///
+    /// Errors returned by `output` are passed through to the caller.
+    fn encode_fmt(&self, input: &str, output: &mut dyn fmt::Write) -> fmt::Result;
+
+    /// Returns the byte offset of the first character in `input` that would be changed by the
+    /// encoder, or `None` if `input` can be written to the output as-is.
+    ///
+    /// Everything before the returned offset is guaranteed to be passed through unchanged, so
+    /// only the remainder has to be run through [`Encoder::encode_into`].
+    fn first_unsafe_index(&self, input: &str) -> Option<usize>;
+
+    /// Returns the length in bytes of the suffix of `input` whose encoding may still change
+    /// depending on the characters that follow it, such as a trailing `]]` that could turn into
+    /// the end of a CDATA section.
+    ///
+    /// Streaming adapters hold this suffix back until more input arrives or the stream ends. Most
+    /// encoders translate every character on its own and use the default of `0`.
+    fn pending_suffix_len(&self, input: &str) -> usize {
+        let _ = input;
+        0
+    }
+
+    /// Returns `true` if encoding `input` would produce anything other than `input` itself.
+    fn needs_encoding(&self, input: &str) -> bool {
+        self.first_unsafe_index(input).is_some()
+    }
+
+    /// Encodes `input`, borrowing it when no character needs to be encoded.
+    ///
+    /// A new `String` is only allocated once the first unsafe character is found; the safe
+    /// prefix is copied over and the encoding starts from that character onward.
+    fn encode_cow<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        match self.first_unsafe_index(input) {
+            None => Cow::Borrowed(input),
+            Some(index) => {
+                let mut result = String::with_capacity(input.len() + (input.len() - index));
+                result.push_str(&input[..index]);
+                self.encode_into(&input[index..], &mut result);
+                Cow::Owned(result)
+            }
+        }
+    }
+
+    /// Encodes `input` into a newly allocated `String`.
+    fn encode(&self, input: &str) -> String {
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Returns a wrapper that writes the encoded form of `input` when it's formatted, so values
+    /// can be interpolated with `format!`/`write!` without an intermediate `String`.
+    ///
+    /// Trait objects can use [`Encoded::new`] instead.
+    ///
+    /// # Example
+    /// ```
+    /// use tiny_clean::encoder::Encoder;
+    /// use tiny_clean::xml_encoder::{XmlEncoder, XmlEncoderMode};
+    ///
+    /// let encoder = XmlEncoder::new(XmlEncoderMode::DoubleQuotedAttribute);
+    /// let title = "\"quoted\" & more";
+    /// assert_eq!(
+    ///     "<a title=\"&#34;quoted&#34; &amp; more\">",
+    ///     format!("<a title=\"{}\">", encoder.display(title))
+    /// );
+    /// ```
+    fn display<'a>(&'a self, input: &'a str) -> Encoded<'a, Self>
+    where
+        Self: Sized,
+    {
+        Encoded::new(self, input)
+    }
+}
+
+/// A string that is encoded lazily, straight into the `fmt::Formatter`, when it's displayed.
+///
+/// Created by [`Encoder::display`] or [`Encoded::new`]. Formatting options such as width and
+/// fill are ignored.
+pub struct Encoded<'a, E: Encoder + ?Sized> {
+    encoder: &'a E,
+    input: &'a str,
+}
+
+impl<'a, E: Encoder + ?Sized> Encoded<'a, E> {
+    pub fn new(encoder: &'a E, input: &'a str) -> Self {
+        Self { encoder, input }
+    }
+}
+
+impl<E: Encoder + ?Sized> Clone for Encoded<'_, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<E: Encoder + ?Sized> Copy for Encoded<'_, E> {}
+
+impl<E: Encoder + ?Sized> fmt::Display for Encoded<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.encoder.encode_fmt(self.input, f)
+    }
+}
+
+impl<E: Encoder + ?Sized> fmt::Debug for Encoded<'_, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encoded")
+            .field("input", &self.input)
+            .finish()
+    }
+}
+
+impl<E: Encoder + ?Sized> Encoder for &E {
+    #[inline]
+    fn encode_into(&self, input: &str, output: &mut String) {
+        (**self).encode_into(input, output)
+    }
+
+    #[inline]
+    fn encode_fmt(&self, input: &str, output: &mut dyn fmt::Write) -> fmt::Result {
+        (**self).encode_fmt(input, output)
+    }
+
+    #[inline]
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        (**self).first_unsafe_index(input)
+    }
+
+    #[inline]
+    fn pending_suffix_len(&self, input: &str) -> usize {
+        (**self).pending_suffix_len(input)
+    }
+
+    #[inline]
+    fn needs_encoding(&self, input: &str) -> bool {
+        (**self).needs_encoding(input)
+    }
+
+    #[inline]
+    fn encode_cow<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        (**self).encode_cow(input)
+    }
+
+    #[inline]
+    fn encode(&self, input: &str) -> String {
+        (**self).encode(input)
+    }
+}
+
+impl<E: Encoder + ?Sized> Encoder for Box<E> {
+    #[inline]
+    fn encode_into(&self, input: &str, output: &mut String) {
+        (**self).encode_into(input, output)
+    }
+
+    #[inline]
+    fn encode_fmt(&self, input: &str, output: &mut dyn fmt::Write) -> fmt::Result {
+        (**self).encode_fmt(input, output)
+    }
+
+    #[inline]
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        (**self).first_unsafe_index(input)
+    }
+
+    #[inline]
+    fn pending_suffix_len(&self, input: &str) -> usize {
+        (**self).pending_suffix_len(input)
+    }
+
+    #[inline]
+    fn needs_encoding(&self, input: &str) -> bool {
+        (**self).needs_encoding(input)
+    }
+
+    #[inline]
+    fn encode_cow<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        (**self).encode_cow(input)
+    }
+
+    #[inline]
+    fn encode(&self, input: &str) -> String {
+        (**self).encode(input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+    use std::fmt;
+
+    use crate::encoder::{Encoded, Encoder};
+    use crate::java_script_encoder::{JavaScriptEncoder, JavaScriptEncoderMode};
+    use crate::uri_encoder::{UriEncoder, UriEncoderMode};
+    use crate::xml_encoder::{XmlEncoder, XmlEncoderMode};
+
+    fn all_encoders() -> Vec<Box<dyn Encoder>> {
+        vec![
+            Box::new(JavaScriptEncoder::new(JavaScriptEncoderMode::Html, true)),
+            Box::new(XmlEncoder::new(XmlEncoderMode::All)),
+            Box::new(UriEncoder::new(UriEncoderMode::Component)),
+        ]
+    }
+
+    #[test]
+    fn test_dyn_encode() {
+        let encoded: Vec<String> = all_encoders().iter().map(|e| e.encode("<'a'>")).collect();
+        assert_eq!(
+            vec!["<\\x27a\\x27>", "&lt;&#39;a&#39;&gt;", "%3C%27a%27%3E"],
+            encoded
+        );
+    }
+
+    #[test]
+    fn test_encode_into_appends() {
+        for encoder in all_encoders() {
+            let mut output = String::from("prefix:");
+            encoder.encode_into("\"x\"", &mut output);
+            assert_eq!(format!("prefix:{}", encoder.encode("\"x\"")), output);
+        }
+    }
+
+    #[test]
+    fn test_encode_fmt() {
+        for encoder in all_encoders() {
+            let mut output = String::from("prefix:");
+            encoder.encode_fmt("a\"b\u{2028}c", &mut output).unwrap();
+            assert_eq!(
+                format!("prefix:{}", encoder.encode("a\"b\u{2028}c")),
+                output
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_fmt_propagates_errors() {
+        struct Limited(usize);
+        impl fmt::Write for Limited {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.0 = self.0.checked_sub(s.len()).ok_or(fmt::Error)?;
+                Ok(())
+            }
+        }
+        for encoder in all_encoders() {
+            assert!(encoder.encode_fmt("abc\"", &mut Limited(3)).is_err());
+            assert!(encoder.encode_fmt("abc", &mut Limited(3)).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::DoubleQuotedAttribute);
+        assert_eq!(
+            "<a title=\"a&amp;b &#34;c&#34;\">",
+            format!("<a title=\"{}\">", encoder.display("a&b \"c\""))
+        );
+        for encoder in all_encoders() {
+            let input = "<'a' & \"b\">\u{2028}";
+            assert_eq!(
+                encoder.encode(input),
+                Encoded::new(&*encoder, input).to_string()
+            );
+            assert_eq!(
+                encoder.encode(input),
+                Encoded::new(&encoder, input).to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_needs_encoding() {
+        for encoder in all_encoders() {
+            assert!(!encoder.needs_encoding("abcABC123"));
+            assert!(!encoder.needs_encoding(""));
+            assert!(encoder.needs_encoding("abc\"def"));
+            assert_eq!(
+                encoder.needs_encoding("a\"b"),
+                encoder.encode("a\"b") != "a\"b"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_cow_borrows_safe_input() {
+        for encoder in all_encoders() {
+            let input = "abcABC123";
+            assert!(matches!(encoder.encode_cow(input), Cow::Borrowed(s) if s == input));
+            assert!(matches!(encoder.encode_cow(""), Cow::Borrowed("")));
+        }
+    }
+
+    #[test]
+    fn test_encode_cow_owns_unsafe_input() {
+        for encoder in all_encoders() {
+            for input in [
+                "\"",
+                "abc\"",
+                "\"abc",
+                "abc\"def\"",
+                "abc\u{1234}\"\u{ffff}",
+            ] {
+                let encoded = encoder.encode_cow(input);
+                assert!(matches!(encoded, Cow::Owned(_)));
+                assert_eq!(encoder.encode(input), encoded);
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_unsafe_index() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content);
+        assert_eq!(None, encoder.first_unsafe_index("abc"));
+        assert_eq!(Some(0), encoder.first_unsafe_index("<abc"));
+        assert_eq!(Some(5), encoder.first_unsafe_index("a\u{e9}bc&"));
+        let encoder = UriEncoder::new(UriEncoderMode::Component);
+        assert_eq!(Some(1), encoder.first_unsafe_index("a\u{e9}bc&"));
+    }
+
+    #[test]
+    fn test_generic_helper() {
+        fn encode_all<E: Encoder>(encoder: E, values: &[&str]) -> String {
+            let mut output = String::new();
+            for value in values {
+                encoder.encode_into(value, &mut output);
+            }
+            output
+        }
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content);
+        assert_eq!("a&amp;b&lt;", encode_all(&encoder, &["a&", "b<"]));
+        let boxed: Box<dyn Encoder> = Box::new(encoder);
+        assert_eq!("a&amp;b&lt;", encode_all(boxed, &["a&", "b<"]));
+    }
+}
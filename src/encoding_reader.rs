@@ -0,0 +1,383 @@
+use crate::encoder::Encoder;
+use std::io::{self, BufRead, Read};
+use std::str;
+
+/// The number of bytes requested from the wrapped reader at a time.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// An [`io::Read`] adapter that yields the encoded form of everything read from the wrapped
+/// reader.
+///
+/// The wrapped reader must produce UTF-8, but reads may end in the middle of a character; the
+/// incomplete sequence is kept until the rest of it arrives. Encoded output that doesn't fit in
+/// the caller's buffer is kept for the next read, so escape sequences are never cut short. Text
+/// whose encoding depends on what comes next (see [`Encoder::pending_suffix_len`]) is held back
+/// until more input is read.
+/// Invalid UTF-8, or a stream that ends in the middle of a character, is reported with an
+/// [`io::ErrorKind::InvalidData`] error.
+///
+/// # Example
+/// ```
+/// use std::io::Read;
+/// use tiny_clean::encoding_reader::EncodingReader;
+/// use tiny_clean::xml_encoder::{XmlEncoder, XmlEncoderMode};
+///
+/// let source: &[u8] = b"fish & chips";
+/// let mut reader = EncodingReader::new(source, XmlEncoder::new(XmlEncoderMode::Content));
+/// let mut output = String::new();
+/// reader.read_to_string(&mut output).unwrap();
+/// assert_eq!("fish &amp; chips", output);
+/// ```
+pub struct EncodingReader<R: Read, E: Encoder> {
+    inner: R,
+    encoder: E,
+    /// Bytes read from `inner` that haven't been encoded yet.
+    input: Vec<u8>,
+    /// Encoded output that hasn't been handed to the caller yet, starting at `output_pos`.
+    output: String,
+    output_pos: usize,
+    eof: bool,
+    /// Set once invalid UTF-8 was read. The error is returned after the output before it.
+    invalid: bool,
+}
+
+impl<R: Read, E: Encoder> EncodingReader<R, E> {
+    pub fn new(inner: R, encoder: E) -> Self {
+        Self {
+            inner,
+            encoder,
+            input: Vec::new(),
+            output: String::new(),
+            output_pos: 0,
+            eof: false,
+            invalid: false,
+        }
+    }
+
+    /// Returns a reference to the wrapped reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the wrapped reader.
+    ///
+    /// Reading from it directly bypasses the encoder.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Returns a reference to the encoder used by this reader.
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    /// Returns the wrapped reader, discarding any buffered input and output.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads from the wrapped reader until some encoded output is available or the end of the
+    /// stream is reached.
+    fn fill_output(&mut self) -> io::Result<()> {
+        if self.invalid {
+            return Err(invalid_utf8());
+        }
+        self.output.clear();
+        self.output_pos = 0;
+        while self.output.is_empty() {
+            if self.eof {
+                if self.input.is_empty() {
+                    return Ok(());
+                }
+                // Whatever is left was held back by the encoder or is an incomplete character.
+                let result = match str::from_utf8(&self.input) {
+                    Ok(input) => {
+                        self.encoder.encode_into(input, &mut self.output);
+                        Ok(())
+                    }
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream ended with an incomplete UTF-8 sequence",
+                    )),
+                };
+                self.input.clear();
+                return result;
+            }
+
+            let start = self.input.len();
+            self.input.resize(start + READ_CHUNK_SIZE, 0);
+            let read = match self.inner.read(&mut self.input[start..]) {
+                Ok(read) => read,
+                Err(e) => {
+                    self.input.truncate(start);
+                    return Err(e);
+                }
+            };
+            self.input.truncate(start + read);
+            if read == 0 {
+                self.eof = true;
+                continue;
+            }
+
+            // An incomplete character after the valid prefix is finished by the next read.
+            let (valid, invalid) = match str::from_utf8(&self.input) {
+                Ok(_) => (self.input.len(), false),
+                Err(e) => (e.valid_up_to(), e.error_len().is_some()),
+            };
+            // The first `valid` bytes were validated by `from_utf8`.
+            let input = str::from_utf8(&self.input[..valid]).map_err(|_| invalid_utf8())?;
+            if invalid {
+                // Nothing after an invalid sequence is read, so no text has to be held back.
+                self.encoder.encode_into(input, &mut self.output);
+                self.input.clear();
+                self.invalid = true;
+                return if self.output.is_empty() {
+                    Err(invalid_utf8())
+                } else {
+                    Ok(())
+                };
+            }
+            // The part that depends on what comes next is kept with the unread input.
+            let split = valid - self.encoder.pending_suffix_len(input);
+            self.encoder.encode_into(&input[..split], &mut self.output);
+            self.input.drain(..split);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read, E: Encoder> Read for EncodingReader<R, E> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read, E: Encoder> BufRead for EncodingReader<R, E> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.output_pos >= self.output.len() {
+            self.fill_output()?;
+        }
+        Ok(&self.output.as_bytes()[self.output_pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.output_pos = (self.output_pos + amt).min(self.output.len());
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoder::Encoder;
+    use crate::encoding_reader::EncodingReader;
+    use crate::java_script_encoder::{JavaScriptEncoder, JavaScriptEncoderMode};
+    use crate::uri_encoder::{UriEncoder, UriEncoderMode};
+    use crate::xml_encoder::{InvalidCharPolicy, XmlEncoder, XmlEncoderMode};
+    use std::io::{self, Read};
+
+    const INPUT: &str = "<a href=\"x\">caf\u{e9} & \u{4e2d}\u{6587} \u{1f600}</a>\n";
+
+    /// Hands out at most `chunk_size` bytes per read.
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.chunk_size.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    fn read_in_chunks<E: Encoder>(encoder: E, input_chunk: usize, output_chunk: usize) -> String {
+        let source = ChunkedReader {
+            data: INPUT.as_bytes(),
+            chunk_size: input_chunk,
+        };
+        let mut reader = EncodingReader::new(source, encoder);
+        let mut output = Vec::new();
+        let mut buf = vec![0u8; output_chunk];
+        loop {
+            let read = reader.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_split_input_and_output() {
+        let xml = XmlEncoder::new(XmlEncoderMode::All);
+        let js = JavaScriptEncoder::new(JavaScriptEncoderMode::Html, true);
+        let uri = UriEncoder::new(UriEncoderMode::Component);
+        for input_chunk in 1..=8 {
+            for output_chunk in [1, 2, 3, 5, 64] {
+                assert_eq!(
+                    xml.encode(INPUT),
+                    read_in_chunks(&xml, input_chunk, output_chunk)
+                );
+                assert_eq!(
+                    js.encode(INPUT),
+                    read_in_chunks(&js, input_chunk, output_chunk)
+                );
+                assert_eq!(
+                    uri.encode(INPUT),
+                    read_in_chunks(&uri, input_chunk, output_chunk)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_io_copy() {
+        let encoder = UriEncoder::new(UriEncoderMode::FullUri);
+        let mut reader = EncodingReader::new(INPUT.as_bytes(), &encoder);
+        let mut output = Vec::new();
+        io::copy(&mut reader, &mut output).unwrap();
+        assert_eq!(encoder.encode(INPUT).as_bytes(), &output[..]);
+    }
+
+    #[test]
+    fn test_held_back_context() {
+        let input = "a]]>b]]]>c]]";
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData);
+        assert_held_back_context(&encoder, input);
+        let input = "a--b---c-";
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment);
+        assert_held_back_context(&encoder, input);
+        let input = "%2f%%e9%g%";
+        let encoder = UriEncoder::new(UriEncoderMode::Normalize);
+        assert_held_back_context(&encoder, input);
+        let input = "a]\u{1}]>b]]\u{1}\u{1}>c-\u{1}-d-\u{1}";
+        for mode in [XmlEncoderMode::CData, XmlEncoderMode::Comment] {
+            for policy in [InvalidCharPolicy::Remove, InvalidCharPolicy::Replace(']')] {
+                let encoder = XmlEncoder::new(mode).with_invalid_char_policy(policy);
+                assert_held_back_context(&encoder, input);
+            }
+        }
+    }
+
+    fn assert_held_back_context(encoder: &dyn Encoder, input: &str) {
+        for input_chunk in 1..=input.len() {
+            let source = ChunkedReader {
+                data: input.as_bytes(),
+                chunk_size: input_chunk,
+            };
+            let mut reader = EncodingReader::new(source, &encoder);
+            let mut output = String::new();
+            reader.read_to_string(&mut output).unwrap();
+            assert_eq!(encoder.encode(input), output);
+        }
+    }
+
+    #[test]
+    fn test_held_back_text_is_bounded() {
+        for (mode, repeated) in [
+            (XmlEncoderMode::CData, "]"),
+            (XmlEncoderMode::Comment, "-"),
+            (XmlEncoderMode::Content, "\u{1}"),
+        ] {
+            let encoder = XmlEncoder::new(mode);
+            let input = repeated.repeat(100_000);
+            let source = ChunkedReader {
+                data: input.as_bytes(),
+                chunk_size: 1000,
+            };
+            let mut reader = EncodingReader::new(source, &encoder);
+            let mut output = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let read = reader.read(&mut buf).unwrap();
+                assert!(reader.input.len() <= 2, "{mode:?}");
+                if read == 0 {
+                    break;
+                }
+                output.extend_from_slice(&buf[..read]);
+            }
+            assert_eq!(encoder.encode(&input).as_bytes(), &output[..]);
+        }
+    }
+
+    /// Hands out `a]\xFF` followed by an endless stream of `z`.
+    struct InvalidAfterHeldBack {
+        reads: usize,
+    }
+
+    impl Read for InvalidAfterHeldBack {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            let data: &[u8] = if self.reads == 1 { b"a]\xFF" } else { b"zzzz" };
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_after_held_back_text() {
+        for mode in [XmlEncoderMode::CData, XmlEncoderMode::Comment] {
+            let source = InvalidAfterHeldBack { reads: 0 };
+            let mut reader = EncodingReader::new(source, XmlEncoder::new(mode));
+            let mut output = Vec::new();
+            let error = reader.read_to_end(&mut output).unwrap_err();
+            assert_eq!("stream did not contain valid UTF-8", error.to_string());
+            assert_eq!(b"a]", &output[..]);
+            assert_eq!(1, reader.get_ref().reads);
+            assert!(reader.input.is_empty());
+            assert!(reader.read(&mut [0u8; 8]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_incomplete_character_at_end() {
+        let source: &[u8] = b"a&b\xE4\xB8";
+        let mut reader = EncodingReader::new(source, XmlEncoder::new(XmlEncoderMode::Content));
+        let mut output = Vec::new();
+        let error = reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!(
+            "stream ended with an incomplete UTF-8 sequence",
+            error.to_string()
+        );
+        assert_eq!(b"a&amp;b", &output[..]);
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let source: &[u8] = b"a<b\xFFc";
+        let mut reader = EncodingReader::new(source, XmlEncoder::new(XmlEncoderMode::Content));
+        let mut output = Vec::new();
+        let error = reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert_eq!("stream did not contain valid UTF-8", error.to_string());
+        assert_eq!(b"a&lt;b", &output[..]);
+
+        // The invalid byte is the last one the stream produces.
+        for chunk_size in 1..=3 {
+            let source = ChunkedReader {
+                data: b"ab\xFF",
+                chunk_size,
+            };
+            let mut reader = EncodingReader::new(source, XmlEncoder::new(XmlEncoderMode::Content));
+            let mut output = Vec::new();
+            let error = reader.read_to_end(&mut output).unwrap_err();
+            assert_eq!("stream did not contain valid UTF-8", error.to_string());
+            assert_eq!(b"ab", &output[..]);
+        }
+    }
+}
@@ -0,0 +1,352 @@
+use crate::encoder::Encoder;
+use std::io::{self, Write};
+use std::{mem, str};
+
+/// An [`io::Write`] adapter that encodes everything written to it before passing it on to the
+/// wrapped writer.
+///
+/// Bytes written to the adapter must be UTF-8, but a character may be split across any number of
+/// `write` calls; the incomplete sequence is buffered until the rest of it arrives. Invalid UTF-8
+/// is rejected with an [`io::ErrorKind::InvalidData`] error. Text whose encoding depends on what
+/// comes next (see [`Encoder::pending_suffix_len`]) is also held back, so the output is the same
+/// no matter how the input is split up.
+///
+/// [`EncodingWriter::finish`] should be called once all input has been written. It writes any
+/// text that was held back, reports a character that was left incomplete and returns the wrapped
+/// writer. [`Write::flush`] reports an incomplete character as well. Like `BufWriter`, the
+/// adapter writes held-back text when it's dropped, but errors are ignored then and an
+/// incomplete character is lost without being reported.
+///
+/// # Example
+/// ```
+/// use std::io::Write;
+/// use tiny_clean::encoding_writer::EncodingWriter;
+/// use tiny_clean::xml_encoder::{XmlEncoder, XmlEncoderMode};
+///
+/// let mut writer = EncodingWriter::new(Vec::new(), XmlEncoder::new(XmlEncoderMode::Content));
+/// writer.write_all(b"fish & chips <\xC3").unwrap();
+/// writer.write_all(b"\xA9>").unwrap();
+/// let output = writer.finish().unwrap();
+/// assert_eq!("fish &amp; chips &lt;\u{e9}&gt;", String::from_utf8(output).unwrap());
+/// ```
+pub struct EncodingWriter<W: Write, E: Encoder> {
+    /// Only `None` once [`EncodingWriter::finish`] has taken it.
+    inner: Option<W>,
+    encoder: E,
+    /// The leading bytes of a character that was split across `write` calls.
+    pending: Vec<u8>,
+    /// Decoded text that can't be encoded until more input arrives.
+    held: String,
+    /// Reused between calls to hold the encoded output before it's written to `inner`.
+    buffer: String,
+}
+
+impl<W: Write, E: Encoder> EncodingWriter<W, E> {
+    pub fn new(inner: W, encoder: E) -> Self {
+        Self {
+            inner: Some(inner),
+            encoder,
+            pending: Vec::with_capacity(4),
+            held: String::new(),
+            buffer: String::new(),
+        }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("writer is only taken by finish")
+    }
+
+    /// Returns a mutable reference to the wrapped writer.
+    ///
+    /// Writing to it directly bypasses the encoder.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("writer is only taken by finish")
+    }
+
+    /// Returns a reference to the encoder used by this writer.
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    /// Encodes any text that was held back, flushes the wrapped writer and returns it.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the input ended in the middle of a character.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.check_no_pending()?;
+        let held = mem::take(&mut self.held);
+        self.write_buffered(&held)?;
+        self.get_mut().flush()?;
+        Ok(self.inner.take().expect("writer is only taken by finish"))
+    }
+
+    /// Encodes `input`, together with any text held back by the previous call, except for the
+    /// suffix that depends on what is written next.
+    fn write_encoded(&mut self, input: &str) -> io::Result<()> {
+        if self.held.is_empty() {
+            let split = input.len() - self.encoder.pending_suffix_len(input);
+            self.held.push_str(&input[split..]);
+            self.write_buffered(&input[..split])
+        } else {
+            let mut text = mem::take(&mut self.held);
+            text.push_str(input);
+            let split = text.len() - self.encoder.pending_suffix_len(&text);
+            self.held.push_str(&text[split..]);
+            self.write_buffered(&text[..split])
+        }
+    }
+
+    /// Fails with [`io::ErrorKind::InvalidData`] if a character is still incomplete.
+    fn check_no_pending(&self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input ends with an incomplete UTF-8 sequence",
+            ))
+        }
+    }
+
+    fn write_buffered(&mut self, input: &str) -> io::Result<()> {
+        if input.is_empty() {
+            return Ok(());
+        }
+        self.buffer.clear();
+        self.encoder.encode_into(input, &mut self.buffer);
+        self.inner
+            .as_mut()
+            .expect("writer is only taken by finish")
+            .write_all(self.buffer.as_bytes())
+    }
+
+    /// Tries to complete the buffered partial character with the first bytes of `buf`.
+    ///
+    /// Returns the number of bytes taken from `buf`.
+    fn complete_pending(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = [0u8; 4];
+        let pending_len = self.pending.len();
+        let available = buf.len().min(4 - pending_len);
+        bytes[..pending_len].copy_from_slice(&self.pending);
+        bytes[pending_len..pending_len + available].copy_from_slice(&buf[..available]);
+
+        let char_len = match str::from_utf8(&bytes[..pending_len + available]) {
+            Ok(_) => pending_len + available,
+            Err(e) if e.valid_up_to() > 0 => e.valid_up_to(),
+            Err(e) if e.error_len().is_none() => {
+                // Still not enough bytes to finish the character.
+                self.pending.extend_from_slice(buf);
+                return Ok(buf.len());
+            }
+            Err(_) => return Err(invalid_utf8()),
+        };
+        self.pending.clear();
+        // The first `char_len` bytes were validated above.
+        let c = str::from_utf8(&bytes[..char_len]).map_err(|_| invalid_utf8())?;
+        self.write_encoded(c)?;
+        Ok(char_len - pending_len)
+    }
+}
+
+impl<W: Write, E: Encoder> Write for EncodingWriter<W, E> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut consumed = 0;
+        if !self.pending.is_empty() {
+            consumed = self.complete_pending(buf)?;
+            if !self.pending.is_empty() {
+                return Ok(consumed);
+            }
+        }
+
+        let rest = &buf[consumed..];
+        match str::from_utf8(rest) {
+            Ok(input) => {
+                self.write_encoded(input)?;
+                consumed = buf.len();
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                // The first `valid` bytes were validated by `from_utf8`.
+                let input = str::from_utf8(&rest[..valid]).map_err(|_| invalid_utf8())?;
+                self.write_encoded(input)?;
+                consumed += valid;
+                match e.error_len() {
+                    None => {
+                        self.pending.extend_from_slice(&rest[valid..]);
+                        consumed = buf.len();
+                    }
+                    Some(_) if consumed == 0 => return Err(invalid_utf8()),
+                    // The invalid sequence is reported by the next call to `write`.
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Flushes the wrapped writer.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidData`] if the input written so far ends in the middle of
+    /// a character; the partial character stays buffered, so writing the rest of it and flushing
+    /// again succeeds. Text that was held back stays buffered too, since its encoding depends on
+    /// what is written next.
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_no_pending()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write, E: Encoder> Drop for EncodingWriter<W, E> {
+    /// Writes any text that was held back, ignoring errors.
+    fn drop(&mut self) {
+        if self.inner.is_some() && !self.held.is_empty() {
+            let held = mem::take(&mut self.held);
+            let _ = self.write_buffered(&held);
+        }
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoder::Encoder;
+    use crate::encoding_writer::EncodingWriter;
+    use crate::java_script_encoder::{JavaScriptEncoder, JavaScriptEncoderMode};
+    use crate::uri_encoder::{UriEncoder, UriEncoderMode};
+    use crate::xml_encoder::{InvalidCharPolicy, XmlEncoder, XmlEncoderMode};
+    use std::io::{self, Write};
+
+    const INPUT: &str = "<a href=\"x\">caf\u{e9} & \u{4e2d}\u{6587} \u{1f600}</a>\n";
+
+    fn write_in_chunks<E: Encoder>(encoder: E, chunk_size: usize) -> String {
+        let mut writer = EncodingWriter::new(Vec::new(), encoder);
+        for chunk in INPUT.as_bytes().chunks(chunk_size) {
+            writer.write_all(chunk).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_split_characters() {
+        let xml = XmlEncoder::new(XmlEncoderMode::All);
+        let js = JavaScriptEncoder::new(JavaScriptEncoderMode::Html, false);
+        let uri = UriEncoder::new(UriEncoderMode::Component);
+        for chunk_size in 1..=INPUT.len() {
+            assert_eq!(xml.encode(INPUT), write_in_chunks(&xml, chunk_size));
+            assert_eq!(js.encode(INPUT), write_in_chunks(&js, chunk_size));
+            assert_eq!(uri.encode(INPUT), write_in_chunks(&uri, chunk_size));
+        }
+    }
+
+    #[test]
+    fn test_io_copy() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content);
+        let mut writer = EncodingWriter::new(Vec::new(), &encoder);
+        io::copy(&mut INPUT.as_bytes(), &mut writer).unwrap();
+        writer.flush().unwrap();
+        let output = writer.finish().unwrap();
+        assert_eq!(encoder.encode(INPUT).as_bytes(), &output[..]);
+    }
+
+    #[test]
+    fn test_held_back_context() {
+        let input = "a]]>b]]]>c]]";
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData);
+        assert_held_back_context(&encoder, input);
+        let input = "a--b---c-";
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment);
+        assert_held_back_context(&encoder, input);
+        let input = "%2f%%e9%g%";
+        let encoder = UriEncoder::new(UriEncoderMode::Normalize);
+        assert_held_back_context(&encoder, input);
+        let input = "a]\u{1}]>b]]\u{1}\u{1}>c-\u{1}-d-\u{1}";
+        for mode in [XmlEncoderMode::CData, XmlEncoderMode::Comment] {
+            for policy in [InvalidCharPolicy::Remove, InvalidCharPolicy::Replace(']')] {
+                let encoder = XmlEncoder::new(mode).with_invalid_char_policy(policy);
+                assert_held_back_context(&encoder, input);
+            }
+        }
+    }
+
+    fn assert_held_back_context(encoder: &dyn Encoder, input: &str) {
+        for chunk_size in 1..=input.len() {
+            let mut writer = EncodingWriter::new(Vec::new(), &encoder);
+            for chunk in input.as_bytes().chunks(chunk_size) {
+                writer.write_all(chunk).unwrap();
+            }
+            let output = writer.finish().unwrap();
+            assert_eq!(encoder.encode(input).as_bytes(), &output[..]);
+        }
+    }
+
+    #[test]
+    fn test_held_back_text_is_bounded() {
+        for (mode, repeated) in [
+            (XmlEncoderMode::CData, "]"),
+            (XmlEncoderMode::Comment, "-"),
+            (XmlEncoderMode::Content, "\u{1}"),
+        ] {
+            let encoder = XmlEncoder::new(mode);
+            let input = repeated.repeat(100_000);
+            let mut writer = EncodingWriter::new(Vec::new(), &encoder);
+            for chunk in input.as_bytes().chunks(1000) {
+                writer.write_all(chunk).unwrap();
+                assert!(writer.held.len() <= 2, "{mode:?}");
+            }
+            let output = writer.finish().unwrap();
+            assert_eq!(encoder.encode(&input).as_bytes(), &output[..]);
+        }
+    }
+
+    #[test]
+    fn test_drop_writes_held_back_text() {
+        let mut output = Vec::new();
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData);
+        let mut writer = EncodingWriter::new(&mut output, &encoder);
+        io::copy(&mut "a]]>b]]".as_bytes(), &mut writer).unwrap();
+        drop(writer);
+        assert_eq!(encoder.encode("a]]>b]]").as_bytes(), &output[..]);
+    }
+
+    #[test]
+    fn test_dangling_partial_character() {
+        let mut writer = EncodingWriter::new(Vec::new(), XmlEncoder::new(XmlEncoderMode::Content));
+        writer.write_all(b"abc\xF0\x9F").unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            writer.flush().unwrap_err().kind()
+        );
+        assert_eq!(b"abc", &writer.get_ref()[..]);
+        writer.write_all(b"\x98\x80").unwrap();
+        writer.flush().unwrap();
+        assert_eq!("abc\u{1f600}".as_bytes(), &writer.get_ref()[..]);
+
+        writer.write_all(b"\xC3").unwrap();
+        let error = writer.finish().unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut writer = EncodingWriter::new(Vec::new(), XmlEncoder::new(XmlEncoderMode::Content));
+        assert_eq!(3, writer.write(b"a<b\xFFc").unwrap());
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            writer.write(b"\xFFc").unwrap_err().kind()
+        );
+        assert_eq!(b"a&lt;b", &writer.get_ref()[..]);
+
+        let mut writer = EncodingWriter::new(Vec::new(), XmlEncoder::new(XmlEncoderMode::Content));
+        writer.write_all(b"\xC3").unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            writer.write(b"a").unwrap_err().kind()
+        );
+    }
+}
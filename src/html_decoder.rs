@@ -0,0 +1,259 @@
+use crate::html_entities::NAMED_REFERENCES;
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HtmlDecoderMode {
+    /// Text between tags.
+    Text,
+    /// An attribute value. A legacy reference without `;` that is followed by `=` or an ASCII
+    /// letter or digit is left alone, so `?a=1&copy=2` in a URL keeps its `&copy`.
+    Attribute,
+}
+
+/// Decodes HTML5 character references the way browsers do.
+///
+/// All named references of the WHATWG table are recognized, including the legacy forms that
+/// browsers accept without a trailing `;` (`&amp`, `&eacute`). Numeric references follow the
+/// HTML5 rules: `&#x80;`-`&#x9f;` are mapped to the Windows-1252 characters they stand for, and
+/// zero, surrogates and out-of-range values become `U+FFFD`. Anything that isn't a reference is
+/// passed through unchanged, so decoding never fails.
+pub struct HtmlDecoder {
+    mode: HtmlDecoderMode,
+}
+
+/// The length of the longest name in [`NAMED_REFERENCES`].
+const MAX_NAME_LEN: usize = 32;
+
+/// What the numeric references `&#x80;`-`&#x9f;` decode to, following Windows-1252. The five
+/// code points that are undefined there are kept as-is.
+const WINDOWS_1252_C1: [char; 32] = [
+    '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}', '\u{17d}', '\u{8f}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}', '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+];
+
+/// A decoded reference.
+enum Decoded {
+    Char(char),
+    Str(&'static str),
+}
+
+impl HtmlDecoder {
+    pub fn new(mode: HtmlDecoderMode) -> Self {
+        Self { mode }
+    }
+
+    /// Decodes every character reference in `input`, borrowing it if there's nothing to decode.
+    pub fn decode<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        let mut output = String::new();
+        let mut copied = 0;
+        let mut search = 0;
+        while let Some(found) = input[search..].find('&') {
+            let start = search + found;
+            match self.parse_reference(&input[start..]) {
+                Some((decoded, len)) => {
+                    if copied == 0 {
+                        output.reserve(input.len());
+                    }
+                    output.push_str(&input[copied..start]);
+                    match decoded {
+                        Decoded::Char(c) => output.push(c),
+                        Decoded::Str(s) => output.push_str(s),
+                    }
+                    copied = start + len;
+                    search = copied;
+                }
+                None => search = start + 1,
+            }
+        }
+        if copied == 0 {
+            return Cow::Borrowed(input);
+        }
+        output.push_str(&input[copied..]);
+        Cow::Owned(output)
+    }
+
+    /// Parses the reference at the start of `input`, which starts with `&`, and returns what it
+    /// decodes to and its length.
+    fn parse_reference(&self, input: &str) -> Option<(Decoded, usize)> {
+        match input[1..].strip_prefix('#') {
+            Some(number) => {
+                let (c, len) = parse_numeric_reference(number)?;
+                Some((Decoded::Char(c), len + 2))
+            }
+            None => {
+                let (value, len) = self.parse_named_reference(&input[1..])?;
+                Some((Decoded::Str(value), len + 1))
+            }
+        }
+    }
+
+    /// Finds the longest named reference at the start of `input`, which follows the `&`.
+    fn parse_named_reference(&self, input: &str) -> Option<(&'static str, usize)> {
+        let bytes = input.as_bytes();
+        let run = bytes
+            .iter()
+            .take(MAX_NAME_LEN)
+            .take_while(|b| b.is_ascii_alphanumeric())
+            .count();
+        if run == 0 {
+            return None;
+        }
+        if bytes.get(run) == Some(&b';')
+            && let Some(value) = lookup(&input[..=run])
+        {
+            return Some((value, run + 1));
+        }
+        // Only the legacy names can match without a `;`.
+        let (value, len) = (1..=run)
+            .rev()
+            .find_map(|len| Some((lookup(&input[..len])?, len)))?;
+        let ambiguous = bytes
+            .get(len)
+            .is_some_and(|&b| b == b'=' || b.is_ascii_alphanumeric());
+        if self.mode == HtmlDecoderMode::Attribute && ambiguous {
+            return None;
+        }
+        Some((value, len))
+    }
+}
+
+fn lookup(name: &str) -> Option<&'static str> {
+    NAMED_REFERENCES
+        .binary_search_by(|&(entry, _)| entry.cmp(name))
+        .ok()
+        .map(|index| NAMED_REFERENCES[index].1)
+}
+
+/// Parses a numeric reference from `input`, which follows the `&#`, and returns the character
+/// it decodes to and its length. The trailing `;` is optional.
+fn parse_numeric_reference(input: &str) -> Option<(char, usize)> {
+    let (prefix_len, radix) = match input.as_bytes().first() {
+        Some(b'x' | b'X') => (1, 16),
+        _ => (0, 10),
+    };
+    let digits = &input[prefix_len..];
+    let digit_count = digits
+        .bytes()
+        .take_while(|&b| (b as char).is_digit(radix))
+        .count();
+    if digit_count == 0 {
+        return None;
+    }
+    let value = digits[..digit_count]
+        .chars()
+        .filter_map(|c| c.to_digit(radix))
+        // Anything above 0x10FFFF decodes to U+FFFD, so stop counting there.
+        .fold(0u32, |value, digit| (value * radix + digit).min(0x110000));
+    let mut len = prefix_len + digit_count;
+    if digits[digit_count..].starts_with(';') {
+        len += 1;
+    }
+    let c = match value {
+        0x80..=0x9F => WINDOWS_1252_C1[value as usize - 0x80],
+        0 => char::REPLACEMENT_CHARACTER,
+        _ => char::from_u32(value).unwrap_or(char::REPLACEMENT_CHARACTER),
+    };
+    Some((c, len))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::html_decoder::{HtmlDecoder, HtmlDecoderMode};
+    use crate::html_encoder::{HtmlEncoder, HtmlEncoderMode};
+    use crate::html_entities::NAMED_REFERENCES;
+    use std::borrow::Cow;
+
+    fn generic_tests(decoder: &HtmlDecoder) {
+        assert_eq!("a\u{a0}b", decoder.decode("a&nbsp;b"));
+        assert_eq!("caf\u{e9}", decoder.decode("caf&eacute;"));
+        assert_eq!(
+            "\u{2aa2}\u{338}",
+            decoder.decode("&NotNestedGreaterGreater;")
+        );
+        assert_eq!("&<>\"'", decoder.decode("&amp;&lt;&gt;&quot;&apos;"));
+        assert_eq!("\u{2242}\u{338}", decoder.decode("&NotEqualTilde;"));
+        assert_eq!("&", decoder.decode("&amp"));
+        assert_eq!("& b", decoder.decode("&amp b"));
+        assert_eq!("\u{2209}", decoder.decode("&notin;"));
+        assert_eq!(
+            "&foo; & &; &#; &#x; &#xg;",
+            decoder.decode("&foo; & &; &#; &#x; &#xg;")
+        );
+        assert_eq!("&Amp;", decoder.decode("&Amp;"));
+        assert_eq!("&AMP", decoder.decode("&AMP;AMP"));
+    }
+
+    fn numeric_tests(decoder: &HtmlDecoder) {
+        assert_eq!("AAA", decoder.decode("&#65;&#x41;&#X41;"));
+        assert_eq!("Ab", decoder.decode("&#65b"));
+        assert_eq!("\u{e9}", decoder.decode("&#x000e9;"));
+        assert_eq!("\u{1f600}", decoder.decode("&#128512;"));
+        assert_eq!(
+            "\u{20ac}\u{81}\u{178}",
+            decoder.decode("&#x80;&#x81;&#159;")
+        );
+        assert_eq!("\u{fffd}", decoder.decode("&#0;"));
+        assert_eq!("\u{fffd}\u{fffd}", decoder.decode("&#xD800;&#xdfff;"));
+        assert_eq!("\u{fffd}", decoder.decode("&#x110000;"));
+        assert_eq!("\u{fffd}", decoder.decode("&#99999999999999999999;"));
+        assert_eq!("\u{10ffff}", decoder.decode("&#x10FFFF;"));
+        assert_eq!("\u{1}\u{fffe}", decoder.decode("&#1;&#xfffe;"));
+    }
+
+    #[test]
+    fn test_text_decode() {
+        let decoder = HtmlDecoder::new(HtmlDecoderMode::Text);
+        assert_eq!("\u{ac}it;", decoder.decode("&notit;"));
+        assert_eq!("&x", decoder.decode("&ampx"));
+        assert_eq!("\u{a9}=2", decoder.decode("&copy=2"));
+        generic_tests(&decoder);
+        numeric_tests(&decoder);
+    }
+
+    #[test]
+    fn test_attribute_decode() {
+        let decoder = HtmlDecoder::new(HtmlDecoderMode::Attribute);
+        assert_eq!("&notit;", decoder.decode("&notit;"));
+        assert_eq!("&ampx", decoder.decode("&ampx"));
+        assert_eq!("?a=1&copy=2", decoder.decode("?a=1&copy=2"));
+        assert_eq!("\u{a9}-2", decoder.decode("&copy-2"));
+        assert_eq!("\u{a9}=2", decoder.decode("&copy;=2"));
+        generic_tests(&decoder);
+        numeric_tests(&decoder);
+    }
+
+    #[test]
+    fn test_cow() {
+        let decoder = HtmlDecoder::new(HtmlDecoderMode::Text);
+        assert!(matches!(decoder.decode("plain & simple"), Cow::Borrowed(_)));
+        assert!(matches!(decoder.decode("&amp;"), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "<a href='x'>\"caf\u{e9}\" & `=` \t\n\u{c}\u{1f600}&amp;</a>";
+        for (encoder_mode, decoder_mode) in [
+            (HtmlEncoderMode::Content, HtmlDecoderMode::Text),
+            (HtmlEncoderMode::Attribute, HtmlDecoderMode::Attribute),
+            (
+                HtmlEncoderMode::UnquotedAttribute,
+                HtmlDecoderMode::Attribute,
+            ),
+        ] {
+            let encoded = HtmlEncoder::new(encoder_mode).encode(input);
+            assert_eq!(input, HtmlDecoder::new(decoder_mode).decode(&encoded));
+        }
+    }
+
+    #[test]
+    fn test_named_reference_table() {
+        assert_eq!(2231, NAMED_REFERENCES.len());
+        assert!(NAMED_REFERENCES.windows(2).all(|w| w[0].0 < w[1].0));
+        let legacy = NAMED_REFERENCES
+            .iter()
+            .filter(|(name, _)| !name.ends_with(';'));
+        assert_eq!(106, legacy.count());
+    }
+}
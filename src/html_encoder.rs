@@ -0,0 +1,201 @@
+use crate::common::{char_bucket, char_mask};
+use crate::encoder::Encoder;
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HtmlEncoderMode {
+    /// Text between tags. Encodes `&`, `<` and `>`.
+    Content,
+    /// An attribute value in single or double quotes. Encodes `&`, `<`, `>`, `'` and `"`.
+    Attribute,
+    /// An attribute value without quotes. On top of the quoted characters, whitespace, `=` and
+    /// the backtick are encoded so the value can't end early or be split into new attributes.
+    ///
+    /// An empty value still has to be written as `attr=""`.
+    UnquotedAttribute,
+}
+
+/// The whitespace characters of the HTML5 input stream: tab, LF, form feed and CR.
+const HTML_WHITESPACE_MASK: u32 =
+    char_mask('\t') | char_mask('\n') | char_mask('\u{c}') | char_mask('\r');
+
+/// Encodes text for HTML5 documents.
+///
+/// Characters that are parse errors in the HTML5 input stream are replaced with `U+FFFD`: C0
+/// controls other than whitespace, DEL, C1 controls and the Unicode noncharacters. They can't be
+/// written as references either, since the parser maps references to C1 controls to Windows-1252
+/// characters (`&#x80;` is `€`). Everything else outside the ASCII range is written as-is.
+pub struct HtmlEncoder {
+    valid_masks: [u32; 4],
+}
+
+impl HtmlEncoder {
+    pub fn new(mode: HtmlEncoderMode) -> Self {
+        let to_be_encoded: &[char] = match mode {
+            HtmlEncoderMode::Content => &['&', '<', '>'],
+            HtmlEncoderMode::Attribute => &['&', '<', '>', '\'', '"'],
+            HtmlEncoderMode::UnquotedAttribute => &[
+                '&', '<', '>', '\'', '"', '`', '=', ' ', '\t', '\n', '\u{c}', '\r',
+            ],
+        };
+        let mut valid_masks = [
+            HTML_WHITESPACE_MASK,
+            u32::MAX,
+            u32::MAX,
+            !char_mask('\u{7f}'),
+        ];
+        for c in to_be_encoded {
+            valid_masks[char_bucket(*c)] &= !char_mask(*c);
+        }
+        Self { valid_masks }
+    }
+
+    pub fn encode(&self, input: &str) -> String {
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Returns `true` if `c` cannot be written to the output as-is.
+    #[inline]
+    fn requires_encoding(&self, c: char) -> bool {
+        if (c as u32) < 128 {
+            self.valid_masks[char_bucket(c)] & char_mask(c) == 0
+        } else {
+            c <= '\u{9f}' || is_noncharacter(c)
+        }
+    }
+
+    /// Returns `true` for characters that are replaced instead of encoded.
+    #[inline]
+    fn is_invalid(&self, c: char) -> bool {
+        if c < ' ' {
+            HTML_WHITESPACE_MASK & char_mask(c) == 0
+        } else {
+            ('\u{7f}'..='\u{9f}').contains(&c) || is_noncharacter(c)
+        }
+    }
+
+    /// Writes the encoded form of `input` to `output`.
+    ///
+    /// Runs of characters that don't need encoding are written with a single `write_str` call.
+    fn write_encoded<W: Write + ?Sized>(&self, input: &str, output: &mut W) -> fmt::Result {
+        let mut safe_start = 0;
+        for (index, c) in input.char_indices() {
+            if !self.requires_encoding(c) {
+                continue;
+            }
+            output.write_str(&input[safe_start..index])?;
+            safe_start = index + c.len_utf8();
+            match c {
+                _ if self.is_invalid(c) => output.write_char(char::REPLACEMENT_CHARACTER)?,
+                '&' => output.write_str("&amp;")?,
+                '<' => output.write_str("&lt;")?,
+                '>' => output.write_str("&gt;")?,
+                '"' => output.write_str("&quot;")?,
+                '\'' => output.write_str("&#39;")?,
+                '`' => output.write_str("&#96;")?,
+                '=' => output.write_str("&#61;")?,
+                _ => write!(output, "&#{};", c as u32)?,
+            }
+        }
+        output.write_str(&input[safe_start..])
+    }
+}
+
+/// Returns `true` for the Unicode noncharacters `U+FDD0`-`U+FDEF` and the last two code points
+/// of every plane.
+#[inline]
+fn is_noncharacter(c: char) -> bool {
+    ('\u{fdd0}'..='\u{fdef}').contains(&c) || (c as u32 & 0xFFFE) == 0xFFFE
+}
+
+impl Encoder for HtmlEncoder {
+    fn encode_into(&self, input: &str, output: &mut String) {
+        // Writing to a `String` never fails.
+        let _ = self.write_encoded(input, output);
+    }
+
+    fn encode_fmt(&self, input: &str, output: &mut dyn Write) -> fmt::Result {
+        self.write_encoded(input, output)
+    }
+
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        input
+            .char_indices()
+            .find(|&(_, c)| self.requires_encoding(c))
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::encoder::Encoder;
+    use crate::html_encoder::{HtmlEncoder, HtmlEncoderMode};
+
+    fn generic_tests(encoder: &HtmlEncoder) {
+        assert_eq!("&amp;&lt;&gt;", encoder.encode("&<>"));
+        assert_eq!("abc", encoder.encode("abc"));
+        assert_eq!(
+            "caf\u{e9}\u{4e2d}\u{1f600}",
+            encoder.encode("caf\u{e9}\u{4e2d}\u{1f600}")
+        );
+        assert_eq!("\u{fffd}", encoder.encode("\u{0}"));
+        assert_eq!("a\u{fffd}b", encoder.encode("a\u{1b}b"));
+        assert_eq!(
+            "\u{fffd}\u{fffd}\u{fffd}",
+            encoder.encode("\u{7f}\u{80}\u{9f}")
+        );
+        assert_eq!("\u{a0}", encoder.encode("\u{a0}"));
+        assert_eq!("\u{fffd}\u{fffd}", encoder.encode("\u{fdd0}\u{fdef}"));
+        assert_eq!(
+            "\u{fdcf}\u{fdf0}\u{fffd}",
+            encoder.encode("\u{fdcf}\u{fdf0}\u{fffd}")
+        );
+        assert_eq!("\u{fffd}\u{fffd}", encoder.encode("\u{fffe}\u{ffff}"));
+        assert_eq!("\u{fffd}\u{fffd}", encoder.encode("\u{1fffe}\u{10ffff}"));
+    }
+
+    #[test]
+    fn test_content_encode() {
+        let encoder = HtmlEncoder::new(HtmlEncoderMode::Content);
+        assert_eq!("'\"`= ", encoder.encode("'\"`= "));
+        assert_eq!("a\tb\nc\u{c}d\re", encoder.encode("a\tb\nc\u{c}d\re"));
+        assert_eq!("&lt;script&gt;", encoder.encode("<script>"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_attribute_encode() {
+        let encoder = HtmlEncoder::new(HtmlEncoderMode::Attribute);
+        assert_eq!("&#39;&quot;", encoder.encode("'\""));
+        assert_eq!("`= \t\n", encoder.encode("`= \t\n"));
+        assert_eq!(
+            "&quot; onload=&quot;alert(1)",
+            encoder.encode("\" onload=\"alert(1)")
+        );
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_unquoted_attribute_encode() {
+        let encoder = HtmlEncoder::new(HtmlEncoderMode::UnquotedAttribute);
+        assert_eq!("&#39;&quot;&#96;&#61;", encoder.encode("'\"`="));
+        assert_eq!(
+            "a&#32;b&#9;c&#10;d&#12;e&#13;f",
+            encoder.encode("a b\tc\nd\u{c}e\rf")
+        );
+        assert_eq!(
+            "x&#32;onload&#61;alert(1)",
+            encoder.encode("x onload=alert(1)")
+        );
+        assert_eq!("a/b", encoder.encode("a/b"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_cow() {
+        let encoder = HtmlEncoder::new(HtmlEncoderMode::Attribute);
+        assert!(!encoder.needs_encoding("plain text \u{e9}"));
+        assert_eq!(Some(5), encoder.first_unsafe_index("plain\u{80}"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a'"));
+    }
+}
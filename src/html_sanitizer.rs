@@ -0,0 +1,591 @@
+use crate::css_sanitizer::{CssPolicy, CssSanitizer};
+use crate::encoder::Encoder;
+use crate::html_decoder::{HtmlDecoder, HtmlDecoderMode};
+use crate::html_encoder::{HtmlEncoder, HtmlEncoderMode};
+use crate::html_tokenizer::{Tag, Token, Tokenizer};
+use crate::uri_validator::UriValidator;
+use std::collections::{HashMap, HashSet};
+
+/// Elements that never have content or an end tag.
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Elements that start foreign content, where `/>` closes an element. On HTML elements it's
+/// ignored, so `<script/>` still starts a script.
+const FOREIGN_ELEMENTS: [&str; 2] = ["math", "svg"];
+
+/// Attributes whose values are URLs and have to pass the scheme allowlist. Every URL in a
+/// `srcset` attribute is checked as well.
+const URL_ATTRIBUTES: [&str; 9] = [
+    "action",
+    "background",
+    "cite",
+    "formaction",
+    "href",
+    "longdesc",
+    "poster",
+    "src",
+    "xlink:href",
+];
+
+/// Elements whose content is removed along with them unless they're allowed.
+const DROPPED_CONTENT_ELEMENTS: [&str; 16] = [
+    "embed",
+    "frame",
+    "frameset",
+    "iframe",
+    "math",
+    "noembed",
+    "noframes",
+    "noscript",
+    "object",
+    "plaintext",
+    "script",
+    "select",
+    "style",
+    "svg",
+    "template",
+    "xmp",
+];
+
+/// Which elements, attributes and URL schemes an [`HtmlSanitizer`] lets through.
+///
+/// Names are matched case-insensitively. Event handler attributes (`onclick`, `onload`, ...)
+/// are always removed, even if they are allowed. Allowed `style` attributes and `<style>`
+/// elements are cleaned with a [`CssSanitizer`], using [`CssPolicy::basic`] unless another
+/// policy is set.
+#[derive(Debug, Clone)]
+pub struct HtmlPolicy {
+    elements: HashSet<String>,
+    attributes: HashMap<String, HashSet<String>>,
+    global_attributes: HashSet<String>,
+    url_schemes: HashSet<String>,
+    dropped_content: HashSet<String>,
+    css: CssPolicy,
+}
+
+impl Default for HtmlPolicy {
+    /// A policy that removes all markup and only keeps the text.
+    fn default() -> Self {
+        Self {
+            elements: HashSet::new(),
+            attributes: HashMap::new(),
+            global_attributes: HashSet::new(),
+            url_schemes: HashSet::new(),
+            dropped_content: DROPPED_CONTENT_ELEMENTS
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            css: CssPolicy::basic(),
+        }
+    }
+}
+
+impl HtmlPolicy {
+    /// A policy that removes all markup and only keeps the text.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy for rich text such as comments: basic formatting, lists, quotes, links and
+    /// images, with `http`, `https` and `mailto` URLs.
+    pub fn basic() -> Self {
+        Self::new()
+            .with_allowed_elements(&[
+                "a",
+                "b",
+                "blockquote",
+                "br",
+                "code",
+                "del",
+                "div",
+                "em",
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "hr",
+                "i",
+                "img",
+                "ins",
+                "li",
+                "ol",
+                "p",
+                "pre",
+                "s",
+                "span",
+                "strong",
+                "sub",
+                "sup",
+                "u",
+                "ul",
+            ])
+            .with_allowed_attributes("a", &["href", "title"])
+            .with_allowed_attributes("blockquote", &["cite"])
+            .with_allowed_attributes("img", &["src", "alt", "title", "width", "height"])
+            .with_allowed_url_schemes(&["http", "https", "mailto"])
+    }
+
+    pub fn with_allowed_elements(mut self, elements: &[&str]) -> Self {
+        self.elements
+            .extend(elements.iter().map(|e| e.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows `attributes` on `element`.
+    pub fn with_allowed_attributes(mut self, element: &str, attributes: &[&str]) -> Self {
+        self.attributes
+            .entry(element.to_ascii_lowercase())
+            .or_default()
+            .extend(attributes.iter().map(|a| a.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows `attributes` on every allowed element.
+    pub fn with_allowed_global_attributes(mut self, attributes: &[&str]) -> Self {
+        self.global_attributes
+            .extend(attributes.iter().map(|a| a.to_ascii_lowercase()));
+        self
+    }
+
+    /// Allows absolute URLs with these schemes in `href`, `src` and other URL attributes.
+    /// Relative URLs are always allowed.
+    pub fn with_allowed_url_schemes(mut self, schemes: &[&str]) -> Self {
+        self.url_schemes
+            .extend(schemes.iter().map(|s| s.to_ascii_lowercase()));
+        self
+    }
+
+    /// Sets the elements whose content is removed together with them when they're not allowed.
+    /// Defaults to `script`, `style` and other elements whose content isn't meant to be read as
+    /// text, such as `template`, `svg` and `iframe`.
+    pub fn with_dropped_content_elements(mut self, elements: &[&str]) -> Self {
+        self.dropped_content = elements.iter().map(|e| e.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Sets the policy for the content of `style` attributes and `<style>` elements.
+    pub fn with_css_policy(mut self, css: CssPolicy) -> Self {
+        self.css = css;
+        self
+    }
+
+    fn is_allowed_attribute(&self, element: &str, attribute: &str) -> bool {
+        !attribute.starts_with("on")
+            && (self.global_attributes.contains(attribute)
+                || self
+                    .attributes
+                    .get(element)
+                    .is_some_and(|allowed| allowed.contains(attribute)))
+    }
+}
+
+/// Cleans untrusted HTML, such as rich-text comments, down to what an [`HtmlPolicy`] allows.
+///
+/// The input is tokenized the way an HTML5 parser would, so markup can't be hidden from the
+/// sanitizer with unusual quoting, comments or raw text elements. Tags and attributes that
+/// aren't allowed are removed, while the text inside removed elements is kept, except for
+/// elements such as `<script>` and `<style>` whose content is dropped too. Comments, doctypes
+/// and processing instructions are removed.
+///
+/// The output is rebuilt rather than copied: text goes through [`HtmlEncoderMode::Content`] and
+/// attribute values through [`HtmlEncoderMode::Attribute`] in double quotes, every allowed
+/// element that is left open is closed and stray end tags are dropped.
+///
+/// # Example
+/// ```
+/// use tiny_clean::html_sanitizer::{HtmlPolicy, HtmlSanitizer};
+///
+/// let sanitizer = HtmlSanitizer::new(HtmlPolicy::basic());
+/// assert_eq!(
+///     "<p>Hi <a href=\"https://example.com\">there</a>!</p>",
+///     sanitizer.sanitize(
+///         "<p onclick=alert(1)>Hi <a href='https://example.com'>there</a>!<script>x()</script>"
+///     )
+/// );
+/// ```
+pub struct HtmlSanitizer {
+    policy: HtmlPolicy,
+    text_decoder: HtmlDecoder,
+    content: HtmlEncoder,
+    attribute: HtmlEncoder,
+    css: CssSanitizer,
+    urls: UriValidator,
+}
+
+impl HtmlSanitizer {
+    pub fn new(policy: HtmlPolicy) -> Self {
+        Self {
+            css: CssSanitizer::new(policy.css.clone()),
+            urls: UriValidator::from_schemes(&policy.url_schemes),
+            policy,
+            text_decoder: HtmlDecoder::new(HtmlDecoderMode::Text),
+            content: HtmlEncoder::new(HtmlEncoderMode::Content),
+            attribute: HtmlEncoder::new(HtmlEncoderMode::Attribute),
+        }
+    }
+
+    pub fn policy(&self) -> &HtmlPolicy {
+        &self.policy
+    }
+
+    pub fn sanitize(&self, input: &str) -> String {
+        let mut output = String::with_capacity(input.len());
+        // Allowed elements that were opened and not closed yet, innermost last.
+        let mut open_elements: Vec<String> = Vec::new();
+        // The element whose content is being dropped and how deeply it's nested in itself.
+        let mut dropping: Option<(String, usize)> = None;
+
+        for token in Tokenizer::new(input) {
+            if let Some((name, depth)) = &mut dropping {
+                match &token {
+                    Token::StartTag(tag) if tag.name == *name && !is_self_closed(tag) => {
+                        *depth += 1
+                    }
+                    Token::EndTag(end) if end == name => {
+                        *depth -= 1;
+                        if *depth == 0 {
+                            dropping = None;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match token {
+                Token::Text(text) => {
+                    let text = self.text_decoder.decode(text);
+                    self.content.encode_into(&text, &mut output);
+                }
+                Token::RawText(text) if open_elements.last().is_some_and(|e| e == "style") => {
+                    output.push_str(&self.css.sanitize_stylesheet(text));
+                }
+                Token::RawText(text) => self.content.encode_into(text, &mut output),
+                Token::StartTag(tag) => {
+                    if self.policy.elements.contains(&tag.name) {
+                        self.write_start_tag(&tag, &mut output);
+                        if !VOID_ELEMENTS.contains(&tag.name.as_str()) {
+                            open_elements.push(tag.name);
+                        }
+                    } else if self.policy.dropped_content.contains(&tag.name)
+                        && !VOID_ELEMENTS.contains(&tag.name.as_str())
+                        && !is_self_closed(&tag)
+                    {
+                        dropping = Some((tag.name, 1));
+                    }
+                }
+                Token::EndTag(name) => {
+                    if let Some(index) = open_elements.iter().rposition(|open| *open == name) {
+                        for open in open_elements.drain(index..).rev() {
+                            write_end_tag(&open, &mut output);
+                        }
+                    }
+                }
+            }
+        }
+        for open in open_elements.iter().rev() {
+            write_end_tag(open, &mut output);
+        }
+        output
+    }
+
+    fn write_start_tag(&self, tag: &Tag, output: &mut String) {
+        output.push('<');
+        output.push_str(&tag.name);
+        for (name, value) in &tag.attributes {
+            if !self.policy.is_allowed_attribute(&tag.name, name)
+                || (URL_ATTRIBUTES.contains(&name.as_str()) && !self.urls.is_allowed(value))
+                || (name == "srcset" && !srcset_urls(value).all(|url| self.urls.is_allowed(url)))
+            {
+                continue;
+            }
+            let css;
+            let value = if name == "style" {
+                css = self.css.sanitize_declarations(value);
+                if css.is_empty() {
+                    continue;
+                }
+                &css
+            } else {
+                value
+            };
+            output.push(' ');
+            output.push_str(name);
+            output.push_str("=\"");
+            self.attribute.encode_into(value, output);
+            output.push('"');
+        }
+        output.push('>');
+    }
+}
+
+/// Returns the URLs of the image candidates in a `srcset` attribute, such as `a.png` and `b.png`
+/// in `a.png 1x, b.png 2x`, split the way browsers parse them.
+fn srcset_urls(value: &str) -> impl Iterator<Item = &str> {
+    let mut rest = value;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (candidate, after) = rest.split_at(end);
+        rest = after;
+        let url = candidate.trim_end_matches(',');
+        if url.len() == candidate.len() {
+            // The descriptors run up to a comma that isn't inside parentheses.
+            let mut in_parens = false;
+            let end = rest
+                .find(|c: char| {
+                    match c {
+                        '(' => in_parens = true,
+                        ')' => in_parens = false,
+                        _ => {}
+                    }
+                    c == ',' && !in_parens
+                })
+                .unwrap_or(rest.len());
+            rest = &rest[end..];
+        }
+        Some(url)
+    })
+}
+
+/// Returns `true` if `tag` has no content because it ends with `/>` and browsers honor that.
+fn is_self_closed(tag: &Tag) -> bool {
+    tag.self_closing && FOREIGN_ELEMENTS.contains(&tag.name.as_str())
+}
+
+fn write_end_tag(name: &str, output: &mut String) {
+    output.push_str("</");
+    output.push_str(name);
+    output.push('>');
+}
+
+#[cfg(test)]
+mod test {
+    use crate::css_sanitizer::CssPolicy;
+    use crate::html_sanitizer::{HtmlPolicy, HtmlSanitizer};
+
+    fn basic() -> HtmlSanitizer {
+        HtmlSanitizer::new(HtmlPolicy::basic())
+    }
+
+    #[test]
+    fn test_text_only() {
+        let sanitizer = HtmlSanitizer::new(HtmlPolicy::new());
+        assert_eq!(
+            "Hello world &amp; friends",
+            sanitizer.sanitize("<p>Hello <b>world</b> &amp; friends</p>")
+        );
+        assert_eq!("a &lt; b", sanitizer.sanitize("a < b"));
+        assert_eq!("caf\u{e9} \u{a0}", sanitizer.sanitize("caf&eacute; &nbsp;"));
+        assert_eq!("x", sanitizer.sanitize("<script>alert(1)</script>x"));
+    }
+
+    #[test]
+    fn test_allowed_markup() {
+        let sanitizer = basic();
+        assert_eq!(
+            "<p>a <b>b</b> <em>c</em><br>d</p>",
+            sanitizer.sanitize("<P>a <B>b</B> <em>c</em><br/>d</p>")
+        );
+        assert_eq!(
+            "<ul><li>one</li><li>two</li></ul>",
+            sanitizer.sanitize("<ul><li>one</li><li>two</li></ul>")
+        );
+        assert_eq!(
+            "<img src=\"/a.png\" alt=\"&quot;a&quot; &amp; b\">",
+            sanitizer.sanitize("<img src=/a.png alt='\"a\" &amp; b' onerror=alert(1)>")
+        );
+        assert_eq!(
+            "<a href=\"x\">first</a>",
+            sanitizer.sanitize("<a href=x href=javascript:alert(1)>first</a>")
+        );
+    }
+
+    #[test]
+    fn test_dropped_content() {
+        let sanitizer = basic();
+        assert_eq!("ab", sanitizer.sanitize("a<script>alert('</p>')</script>b"));
+        assert_eq!(
+            "ab",
+            sanitizer.sanitize("a<style>p { color: red }</style>b")
+        );
+        assert_eq!("ab", sanitizer.sanitize("a<SCRIPT>x</script >b"));
+        assert_eq!("a", sanitizer.sanitize("a<script>never closed"));
+        assert_eq!("ab", sanitizer.sanitize("a<svg><svg></svg><b>x</b></svg>b"));
+        assert_eq!(
+            "ab",
+            sanitizer.sanitize("a<template><script>x</script></template>b")
+        );
+        assert_eq!("ac", sanitizer.sanitize("a<object/><b>x</b></object>c"));
+        assert_eq!("a<b>x</b>", sanitizer.sanitize("a<svg/><b>x</b>"));
+        assert_eq!(
+            "<p>ab</p>",
+            sanitizer.sanitize("<p>a<script/>alert(document.cookie)</script>b</p>")
+        );
+        assert_eq!(
+            "ab",
+            sanitizer.sanitize("a<style/>body { color: red }</style>b")
+        );
+        assert_eq!("ab", sanitizer.sanitize("a<noscript/><b>x</b></noscript>b"));
+        assert_eq!(
+            "ab",
+            sanitizer.sanitize("a<object><object/>x</object>y</object>b")
+        );
+        assert_eq!("a", sanitizer.sanitize("a<noscript><b></noscript>"));
+        assert_eq!(
+            "&lt;b&gt;x&lt;/b&gt;",
+            HtmlSanitizer::new(HtmlPolicy::new().with_dropped_content_elements(&[]))
+                .sanitize("<xmp><b>x</b></xmp>")
+        );
+    }
+
+    #[test]
+    fn test_event_handlers_and_urls() {
+        let sanitizer = HtmlSanitizer::new(
+            HtmlPolicy::basic()
+                .with_allowed_attributes("a", &["onclick"])
+                .with_allowed_global_attributes(&["onmouseover", "class"]),
+        );
+        assert_eq!(
+            "<a class=\"c\">x</a>",
+            sanitizer.sanitize("<a onclick=\"a()\" onmouseover=b() class=c>x</a>")
+        );
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " javascript:alert(1)",
+            "java\tscript:alert(1)",
+            "java&#x09;script:alert(1)",
+            "&#106;avascript:alert(1)",
+            "javascript&colon;alert(1)",
+            "\u{1}javascript:alert(1)",
+            "vbscript:msgbox(1)",
+            "data:text/html,<script>alert(1)</script>",
+            "1javascript:alert(1)",
+        ] {
+            let input = format!("<a href=\"{}\">x</a>", url);
+            assert_eq!("<a>x</a>", sanitizer.sanitize(&input), "{url:?}");
+        }
+        for url in [
+            "https://example.com/a?b=c&amp;d=e",
+            "HTTP://example.com",
+            "mailto:a@example.com",
+            "/relative/path:with-colon",
+            "page.html#a:b",
+            "?q=a:b",
+            "",
+        ] {
+            let input = format!("<a href=\"{}\">x</a>", url);
+            assert!(sanitizer.sanitize(&input).contains("href"), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn test_srcset() {
+        let sanitizer =
+            HtmlSanitizer::new(HtmlPolicy::basic().with_allowed_attributes("img", &["srcset"]));
+        for srcset in [
+            "a.png",
+            "a.png 1x, https://example.com/b.png 2x",
+            "a.png 100w,b.png 200w",
+            "a.png (x, y) 1x, b.png",
+            "a.png,, b.png,",
+        ] {
+            let input = format!("<img srcset=\"{}\">", srcset);
+            assert!(sanitizer.sanitize(&input).contains("srcset"), "{srcset:?}");
+        }
+        for srcset in [
+            "javascript:alert(1)",
+            "a.png 1x, javascript:alert(1) 2x",
+            "a.png 1x,javascript:alert(1)",
+            "a.png, javascript:alert(1)",
+            "a.png (1x, y), data:image/svg+xml,x",
+        ] {
+            let input = format!("<img srcset=\"{}\">", srcset);
+            assert_eq!("<img>", sanitizer.sanitize(&input), "{srcset:?}");
+        }
+    }
+
+    #[test]
+    fn test_tokenizer_edge_cases() {
+        let sanitizer = basic();
+        assert_eq!(
+            "ab",
+            sanitizer.sanitize("a<!-- <script>alert(1)</script> -->b")
+        );
+        assert_eq!("a<b>b</b>", sanitizer.sanitize("a<!--><b>b"));
+        assert_eq!("a<b>b</b>", sanitizer.sanitize("a<!---><b>b"));
+        assert_eq!("ab", sanitizer.sanitize("a<!doctype html>b"));
+        assert_eq!("ab", sanitizer.sanitize("a<?php echo 1 ?>b"));
+        assert_eq!("ab", sanitizer.sanitize("a</ script>b"));
+        assert_eq!("a&lt; b", sanitizer.sanitize("a< b"));
+        assert_eq!("a", sanitizer.sanitize("a<b title=\"unterminated>"));
+        assert_eq!("a", sanitizer.sanitize("a<img src=x onerror=alert(1)//"));
+        assert_eq!("<p>x</p>", sanitizer.sanitize("<p/onclick=alert(1)>x</p>"));
+        assert_eq!(
+            "<a href=\"x\">y</a>",
+            sanitizer.sanitize("<a\nhref\n=\n'x'\n>y</a>")
+        );
+        assert_eq!(
+            "<p>&lt;/p&gt;</p>",
+            sanitizer.sanitize("<p><title></p></title></p>")
+        );
+    }
+
+    #[test]
+    fn test_styles() {
+        let sanitizer = HtmlSanitizer::new(
+            HtmlPolicy::basic()
+                .with_allowed_elements(&["style"])
+                .with_allowed_global_attributes(&["style"]),
+        );
+        assert_eq!(
+            "<p style=\"color: red; font-family: &quot;a&quot;\">x</p>",
+            sanitizer.sanitize(
+                "<p style=\"color:red;width:expression(alert(1));font-family:'a'\">x</p>"
+            )
+        );
+        assert_eq!(
+            "<p>x</p>",
+            sanitizer.sanitize("<p style='behavior:url(x.htc)'>x</p>")
+        );
+        assert_eq!(
+            "<style>p { color: red }</style>",
+            sanitizer.sanitize("<style>@import 'evil.css'; p { color: red }</style>")
+        );
+        assert_eq!(
+            "<style>a { font-family: \"\\3c/style\\3e \" }</style>",
+            sanitizer.sanitize("<style>a { font-family: '\\3c/style\\3e' }</style>")
+        );
+        assert_eq!(
+            "<p style=\"margin: 0\">x</p>",
+            HtmlSanitizer::new(
+                HtmlPolicy::basic()
+                    .with_allowed_global_attributes(&["style"])
+                    .with_css_policy(CssPolicy::new().with_allowed_properties(&["margin"])),
+            )
+            .sanitize("<p style='color: red; margin: 0'>x</p>")
+        );
+    }
+
+    #[test]
+    fn test_balancing() {
+        let sanitizer = basic();
+        assert_eq!("<b><i>x</i></b>", sanitizer.sanitize("<b><i>x</b>"));
+        assert_eq!("<p>x</p>", sanitizer.sanitize("</b><p>x"));
+        assert_eq!(
+            "<div>x</div>",
+            sanitizer.sanitize("<div>x</div></div></span>")
+        );
+        assert_eq!("<p></p>", sanitizer.sanitize("<p/>"));
+    }
+}
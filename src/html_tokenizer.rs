@@ -0,0 +1,433 @@
+//! A tokenizer following the HTML5 tokenization rules closely enough to find the same tags,
+//! attributes and text as a browser. Comments, doctypes and processing instructions are
+//! recognized so they can't hide markup, but they're not reported.
+
+use crate::html_decoder::{HtmlDecoder, HtmlDecoderMode};
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token<'a> {
+    /// Text in which character references have not been decoded yet.
+    Text(&'a str),
+    /// The content of a raw text element such as `<script>` or `<style>`, taken literally.
+    RawText(&'a str),
+    StartTag(Tag),
+    EndTag(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Tag {
+    /// The lowercased tag name.
+    pub(crate) name: String,
+    /// Lowercased attribute names with decoded values, without duplicates.
+    pub(crate) attributes: Vec<(String, String)>,
+    pub(crate) self_closing: bool,
+}
+
+/// How the content of an element is tokenized.
+#[derive(Clone, Copy, PartialEq)]
+enum ContentModel {
+    /// Nothing but its end tag ends the element; character references are decoded.
+    RcData,
+    /// Nothing but its end tag ends the element; the content is taken literally.
+    RawText,
+    /// Everything up to the end of the input is literal text.
+    PlainText,
+}
+
+fn content_model(name: &str) -> Option<ContentModel> {
+    match name {
+        "textarea" | "title" => Some(ContentModel::RcData),
+        "script" | "style" | "xmp" | "iframe" | "noembed" | "noframes" | "noscript" => {
+            Some(ContentModel::RawText)
+        }
+        "plaintext" => Some(ContentModel::PlainText),
+        _ => None,
+    }
+}
+
+pub(crate) struct Tokenizer<'a> {
+    input: &'a str,
+    pos: usize,
+    /// Set after the start tag of an element whose content isn't parsed as markup.
+    content: Option<(ContentModel, String)>,
+    attribute_decoder: HtmlDecoder,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            pos: 0,
+            content: None,
+            attribute_decoder: HtmlDecoder::new(HtmlDecoderMode::Attribute),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    /// Returns the text of a raw text, RCDATA or plaintext element, up to its end tag.
+    fn element_content(&mut self, model: ContentModel, name: &str) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let len = match model {
+            ContentModel::PlainText => rest.len(),
+            _ => find_end_tag(rest, name).unwrap_or(rest.len()),
+        };
+        self.pos += len;
+        if len == 0 {
+            return None;
+        }
+        let text = &rest[..len];
+        Some(match model {
+            ContentModel::RcData => Token::Text(text),
+            _ => Token::RawText(text),
+        })
+    }
+
+    /// Tokenizes the markup at the current position, which starts with `<`. Returns `None` if
+    /// it's a comment or similar construct, which is skipped.
+    fn markup(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        match bytes.get(1) {
+            Some(b) if b.is_ascii_alphabetic() => {
+                self.pos += 1;
+                self.tag(false)
+            }
+            Some(b'/') => match bytes.get(2) {
+                Some(b) if b.is_ascii_alphabetic() => {
+                    self.pos += 2;
+                    self.tag(true)
+                }
+                Some(b'>') => {
+                    self.pos += 3;
+                    None
+                }
+                None => {
+                    self.pos = self.input.len();
+                    Some(Token::Text(rest))
+                }
+                Some(_) => {
+                    self.skip_bogus_comment(2);
+                    None
+                }
+            },
+            Some(b'!') if rest.starts_with("<!--") => {
+                self.skip_comment();
+                None
+            }
+            Some(b'!' | b'?') => {
+                self.skip_bogus_comment(2);
+                None
+            }
+            _ => {
+                self.pos += 1;
+                Some(Token::Text(&rest[..1]))
+            }
+        }
+    }
+
+    fn skip_bogus_comment(&mut self, offset: usize) {
+        let rest = &self.rest()[offset..];
+        self.pos += offset + rest.find('>').map_or(rest.len(), |end| end + 1);
+    }
+
+    fn skip_comment(&mut self) {
+        let body = &self.rest()[4..];
+        // `<!-->` and `<!--->` are complete (empty) comments.
+        let len = if body.starts_with('>') {
+            1
+        } else if body.starts_with("->") {
+            2
+        } else {
+            let double_dash = body.find("-->").map(|end| end + 3);
+            let bang = body.find("--!>").map(|end| end + 4);
+            match (double_dash, bang) {
+                (Some(a), Some(b)) => a.min(b),
+                (end, None) | (None, end) => end.unwrap_or(body.len()),
+            }
+        };
+        self.pos += 4 + len;
+    }
+
+    /// Tokenizes a tag, starting at its name. A tag cut off by the end of the input is dropped,
+    /// together with the rest of the input.
+    fn tag(&mut self, end_tag: bool) -> Option<Token<'a>> {
+        let token = self.parse_tag(end_tag);
+        if token.is_none() {
+            self.pos = self.input.len();
+        }
+        token
+    }
+
+    fn parse_tag(&mut self, end_tag: bool) -> Option<Token<'a>> {
+        let name = self.take_name(|b| b.is_ascii_whitespace() || b == b'/' || b == b'>');
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        let mut self_closing = false;
+        loop {
+            let &b = self.rest().as_bytes().first()?;
+            match b {
+                b'>' => {
+                    self.pos += 1;
+                    break;
+                }
+                b'/' => {
+                    self.pos += 1;
+                    self_closing = self.rest().starts_with('>');
+                }
+                _ if b.is_ascii_whitespace() => self.pos += 1,
+                _ => {
+                    let (name, value) = self.attribute()?;
+                    if !attributes.iter().any(|(existing, _)| *existing == name) {
+                        attributes.push((name, value));
+                    }
+                }
+            }
+        }
+        if end_tag {
+            return Some(Token::EndTag(name));
+        }
+        if let Some(model) = content_model(&name) {
+            self.content = Some((model, name.clone()));
+        }
+        Some(Token::StartTag(Tag {
+            name,
+            attributes,
+            self_closing,
+        }))
+    }
+
+    /// Tokenizes an attribute, starting at its name.
+    fn attribute(&mut self) -> Option<(String, String)> {
+        // A leading `=` is part of the name.
+        let first = self.rest().chars().next()?;
+        self.pos += first.len_utf8();
+        let mut name = first.to_ascii_lowercase().to_string();
+        name.push_str(
+            &self.take_name(|b| b.is_ascii_whitespace() || b == b'/' || b == b'>' || b == b'='),
+        );
+        self.skip_whitespace();
+        if !self.rest().starts_with('=') {
+            return Some((name, String::new()));
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+        let rest = self.rest();
+        let raw = match rest.as_bytes().first()? {
+            &quote @ (b'"' | b'\'') => {
+                let end = rest[1..].find(quote as char)?;
+                self.pos += end + 2;
+                &rest[1..=end]
+            }
+            _ => {
+                let end = rest
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                self.pos += end;
+                &rest[..end]
+            }
+        };
+        Some((name, self.attribute_decoder.decode(raw).into_owned()))
+    }
+
+    /// Skips the HTML whitespace characters (tab, LF, form feed, CR and space).
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len()
+            - rest
+                .trim_start_matches(|c: char| c.is_ascii_whitespace())
+                .len();
+    }
+
+    /// Consumes a tag or attribute name up to a byte matching `end`, lowercasing ASCII letters
+    /// and replacing NUL with `U+FFFD`.
+    fn take_name(&mut self, end: impl Fn(u8) -> bool) -> String {
+        let rest = self.rest();
+        let len = rest.bytes().position(end).unwrap_or(rest.len());
+        self.pos += len;
+        rest[..len].to_ascii_lowercase().replace('\0', "\u{fffd}")
+    }
+}
+
+/// Finds the `</name` that ends a raw text or RCDATA element, followed by whitespace, `/` or `>`.
+fn find_end_tag(input: &str, name: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let mut search = 0;
+    while let Some(found) = input[search..].find("</") {
+        let start = search + found;
+        let name_end = start + 2 + name.len();
+        if bytes.len() >= name_end
+            && bytes[start + 2..name_end].eq_ignore_ascii_case(name.as_bytes())
+            && bytes
+                .get(name_end)
+                .is_none_or(|&b| b.is_ascii_whitespace() || b == b'/' || b == b'>')
+        {
+            return Some(start);
+        }
+        search = start + 2;
+    }
+    None
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        if let Some((model, name)) = self.content.take()
+            && let Some(token) = self.element_content(model, &name)
+        {
+            return Some(token);
+        }
+        while self.pos < self.input.len() {
+            let rest = self.rest();
+            match rest.find('<') {
+                Some(0) => {
+                    if let Some(token) = self.markup() {
+                        return Some(token);
+                    }
+                }
+                Some(end) => {
+                    self.pos += end;
+                    return Some(Token::Text(&rest[..end]));
+                }
+                None => {
+                    self.pos = self.input.len();
+                    return Some(Token::Text(rest));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::html_tokenizer::{Tag, Token, Tokenizer};
+
+    fn tokens(input: &str) -> Vec<Token<'_>> {
+        Tokenizer::new(input).collect()
+    }
+
+    fn start_tag(name: &str, attributes: &[(&str, &str)]) -> Token<'static> {
+        Token::StartTag(Tag {
+            name: name.into(),
+            attributes: attributes
+                .iter()
+                .map(|&(name, value)| (name.into(), value.into()))
+                .collect(),
+            self_closing: false,
+        })
+    }
+
+    #[test]
+    fn test_raw_text_end_tags() {
+        let style = || start_tag("style", &[]);
+        let end_style = || Token::EndTag("style".into());
+        assert_eq!(
+            vec![
+                style(),
+                Token::RawText("a<b>"),
+                end_style(),
+                Token::Text("c")
+            ],
+            tokens("<style>a<b></style >c")
+        );
+        assert_eq!(
+            vec![style(), Token::RawText("a"), end_style()],
+            tokens("<style>a</STYLE>")
+        );
+        assert_eq!(
+            vec![style(), Token::RawText("a</styled>b"), end_style()],
+            tokens("<style>a</styled>b</style/>")
+        );
+        // An end tag cut off by the end of the input is dropped like any other tag.
+        assert_eq!(
+            vec![style(), Token::RawText("a")],
+            tokens("<style>a</style")
+        );
+        assert_eq!(vec![style(), end_style()], tokens("<style></style>"));
+        assert_eq!(
+            vec![
+                start_tag("title", &[]),
+                Token::Text("&amp;<b></titles>"),
+                Token::EndTag("title".into()),
+            ],
+            tokens("<title>&amp;<b></titles></TITLE>")
+        );
+        assert_eq!(
+            vec![start_tag("plaintext", &[]), Token::RawText("</plaintext>")],
+            tokens("<plaintext></plaintext>")
+        );
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            vec![start_tag(
+                "a",
+                &[
+                    ("href", "/x?a=1&b=2"),
+                    ("title", "it's \"q\""),
+                    ("data-x", "y"),
+                    ("hidden", ""),
+                ]
+            )],
+            tokens("<A HREF=/x?a=1&amp;b=2 title='it&apos;s \"q\"' data-x = \"y\" hidden>")
+        );
+        assert_eq!(
+            vec![start_tag("a", &[("href", "first"), ("title", "t")])],
+            tokens("<a href=first HREF='second' title=t href=\"third\">")
+        );
+        assert_eq!(
+            vec![start_tag("p", &[("onclick", "alert(1)")])],
+            tokens("<p/onclick=alert(1)>")
+        );
+        assert_eq!(vec![start_tag("a", &[("=b", "c")])], tokens("<a =b=c>"));
+        assert_eq!(
+            vec![Token::StartTag(Tag {
+                name: "br".into(),
+                attributes: Vec::new(),
+                self_closing: true,
+            })],
+            tokens("<br/>")
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(vec![Token::Text("a"), Token::Text("b")], tokens("a<!-->b"));
+        assert_eq!(vec![Token::Text("a"), Token::Text("b")], tokens("a<!--->b"));
+        assert_eq!(
+            vec![Token::Text("a"), Token::Text("c")],
+            tokens("a<!-- <b> --!>c")
+        );
+        assert_eq!(vec![Token::Text("a")], tokens("a<!-- <b>"));
+        assert_eq!(
+            vec![
+                Token::Text("a"),
+                Token::Text("b"),
+                Token::Text("c"),
+                Token::Text("d")
+            ],
+            tokens("a<!doctype html>b<?php x ?>c</ x>d")
+        );
+        assert_eq!(vec![Token::Text("a"), Token::Text("b")], tokens("a</>b"));
+    }
+
+    #[test]
+    fn test_end_of_input() {
+        assert_eq!(vec![Token::Text("a")], tokens("a<b title=\"x>"));
+        assert_eq!(
+            vec![Token::Text("a")],
+            tokens("a<img src=x onerror=alert(1)//")
+        );
+        assert_eq!(vec![Token::Text("a")], tokens("a</b"));
+        assert_eq!(vec![Token::Text("a"), Token::Text("</")], tokens("a</"));
+        assert_eq!(vec![Token::Text("a"), Token::Text("<")], tokens("a<"));
+        assert_eq!(
+            vec![Token::Text("a"), Token::Text("<"), Token::Text(" b")],
+            tokens("a< b")
+        );
+    }
+}
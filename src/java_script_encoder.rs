@@ -1,4 +1,9 @@
-use crate::common::{char_bucket, char_mask, encode_as_hex_byte, encode_as_unicode, dump_masks_to_ascii};
+use crate::common::{
+    char_bucket, char_mask, dump_masks_to_ascii, encode_as_hex_byte, encode_as_unicode,
+    encode_as_unicode_code_point,
+};
+use crate::encoder::Encoder;
+use std::fmt::{self, Write};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JavaScriptEncoderMode {
@@ -12,9 +17,11 @@ pub struct JavaScriptEncoder {
     ascii_only: bool,
     valid_masks: [u32; 4],
     hex_encode_quotes: bool,
+    code_point_escapes: bool,
 }
 
 impl JavaScriptEncoder {
+    #[allow(clippy::identity_op)]
     pub fn new(mode: JavaScriptEncoderMode, ascii_only: bool) -> Self {
         let mut valid_masks = [
             0,
@@ -40,82 +47,92 @@ impl JavaScriptEncoder {
             dump_masks_to_ascii(&valid_masks);
         }
 
-        let hex_encode_quotes = mode == JavaScriptEncoderMode::Attribute || mode == JavaScriptEncoderMode::Html;
+        let hex_encode_quotes =
+            mode == JavaScriptEncoderMode::Attribute || mode == JavaScriptEncoderMode::Html;
         JavaScriptEncoder {
             ascii_only,
             valid_masks,
             hex_encode_quotes,
+            code_point_escapes: false,
         }
     }
+
+    /// Escapes characters beyond the Basic Multilingual Plane with the ES2015 code point syntax
+    /// (`\u{1f600}`) instead of a UTF-16 surrogate pair (`\ud83d\ude00`).
+    ///
+    /// Only use this when the output is consumed by an ES2015 (or later) engine; older engines
+    /// don't understand code point escapes. It only has an effect when `ascii_only` is set, since
+    /// these characters are written as-is otherwise.
+    pub fn with_code_point_escapes(mut self, enabled: bool) -> Self {
+        self.code_point_escapes = enabled;
+        self
+    }
+
     const LINE_SEPARATOR: char = '\u{2028}';
     const PARAGRAPH_SEPARATOR: char = '\u{2029}';
 
     pub fn encode(&self, input: &str) -> String {
-        let starting_capacity = (u32::MAX / 2).min((input.len() * 6) as u32) as usize;
-        let mut result = String::with_capacity(starting_capacity);
-        for c in input.chars() {
-            if c as u32 <= 127 {
-                let mask_index = char_bucket(c);
-                let character_mask = char_mask(c);
-
-                if (self.valid_masks[mask_index] & character_mask) == 0 {
-                    match c {
-                        '\u{0008}' => {
-                            result.push_str("\\b");
-                            continue;
-                        }
-                        '\u{0009}' => {
-                            result.push_str("\\t");
-                            continue;
-                        }
-                        '\u{000a}' => {
-                            result.push_str("\\n");
-                            continue;
-                        }
-                        '\u{000c}' => {
-                            result.push_str("\\f");
-                            continue;
-                        }
-                        '\u{000d}' => {
-                            result.push_str("\\r");
-                            continue;
-                        }
-                        '\'' | '"' => {
-                            if self.hex_encode_quotes {
-                                encode_as_hex_byte('\\', &mut result, c);
-                                continue;
-                            } else {
-                                result.push('\\');
-                                result.push(c);
-                                continue;
-                            }
-                        }
-                        '\\' | '/' | '-' => {
-                            result.push('\\');
-                            result.push(c);
-                            continue;
-                        }
-                        _ => {
-                            encode_as_hex_byte('\\', &mut result, c);
-                            continue;
-                        }
-                    }
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Returns `true` if `c` cannot be written to the output as-is.
+    #[inline]
+    fn requires_encoding(&self, c: char) -> bool {
+        if c as u32 <= 127 {
+            (self.valid_masks[char_bucket(c)] & char_mask(c)) == 0
+        } else {
+            self.ascii_only || c == Self::LINE_SEPARATOR || c == Self::PARAGRAPH_SEPARATOR
+        }
+    }
+
+    /// Writes the encoded form of `input` to `output`.
+    ///
+    /// Runs of characters that don't need encoding are written with a single `write_str` call.
+    fn write_encoded<W: Write + ?Sized>(&self, input: &str, output: &mut W) -> fmt::Result {
+        let mut safe_start = 0;
+        for (index, c) in input.char_indices() {
+            if !self.requires_encoding(c) {
+                continue;
+            }
+            output.write_str(&input[safe_start..index])?;
+            safe_start = index + c.len_utf8();
+            match c {
+                '\u{0008}' => output.write_str("\\b")?,
+                '\u{0009}' => output.write_str("\\t")?,
+                '\u{000a}' => output.write_str("\\n")?,
+                '\u{000c}' => output.write_str("\\f")?,
+                '\u{000d}' => output.write_str("\\r")?,
+                '\'' | '"' if self.hex_encode_quotes => encode_as_hex_byte('\\', output, c)?,
+                '\'' | '"' | '\\' | '/' | '-' => {
+                    output.write_char('\\')?;
+                    output.write_char(c)?;
                 }
-            } else if self.ascii_only || c == Self::LINE_SEPARATOR || c == Self::PARAGRAPH_SEPARATOR
-            {
-                if c as u32 <= 0xFF {
-                    encode_as_hex_byte('\\', &mut result, c);
-                    continue;
-                } else {
-                    encode_as_unicode('\\', &mut result, c);
-                    continue;
+                _ if c as u32 <= 0xFF => encode_as_hex_byte('\\', output, c)?,
+                _ if c as u32 > 0xFFFF && self.code_point_escapes => {
+                    encode_as_unicode_code_point('\\', output, c)?
                 }
+                _ => encode_as_unicode('\\', output, c)?,
             }
-            result.push(c);
         }
+        output.write_str(&input[safe_start..])
+    }
+}
+
+impl Encoder for JavaScriptEncoder {
+    fn encode_into(&self, input: &str, output: &mut String) {
+        // Writing to a `String` never fails.
+        let _ = self.write_encoded(input, output);
+    }
+
+    fn encode_fmt(&self, input: &str, output: &mut dyn Write) -> fmt::Result {
+        self.write_encoded(input, output)
+    }
 
-        result.shrink_to_fit();
-        result
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        input
+            .char_indices()
+            .find(|&(_, c)| self.requires_encoding(c))
+            .map(|(index, _)| index)
     }
 }
 
@@ -138,10 +155,31 @@ mod test {
     fn ascii_only_tests(encoder: &JavaScriptEncoder) {
         assert_eq!("\\u1234", encoder.encode("\u{1234}"));
         assert_eq!("\\xff", encoder.encode("\u{ff}"));
+        assert_eq!("\\uffff", encoder.encode("\u{ffff}"));
+        assert_eq!("\\ud800\\udc00", encoder.encode("\u{10000}"));
+        assert_eq!("\\ud83d\\ude00", encoder.encode("\u{1f600}"));
+        assert_eq!("a\\ud83d\\ude00b", encoder.encode("a\u{1f600}b"));
+        assert_eq!("\\udbff\\udfff", encoder.encode("\u{10ffff}"));
     }
 
     fn ascii_extended_tests(encoder: &JavaScriptEncoder) {
         assert_eq!("\u{00ff}", encoder.encode("\u{00ff}"));
+        assert_eq!("\u{1f600}", encoder.encode("\u{1f600}"));
+        assert_eq!("\u{10ffff}", encoder.encode("\u{10ffff}"));
+    }
+
+    fn code_point_escape_tests(mode: JavaScriptEncoderMode, ascii_only: bool) {
+        let encoder = JavaScriptEncoder::new(mode, ascii_only).with_code_point_escapes(true);
+        if ascii_only {
+            assert_eq!("\\u{10000}", encoder.encode("\u{10000}"));
+            assert_eq!("\\u{1f600}", encoder.encode("\u{1f600}"));
+            assert_eq!("\\u{10ffff}", encoder.encode("\u{10ffff}"));
+            assert_eq!("\\u1234", encoder.encode("\u{1234}"));
+            assert_eq!("\\xff", encoder.encode("\u{ff}"));
+        } else {
+            assert_eq!("\u{1f600}", encoder.encode("\u{1f600}"));
+        }
+        assert_eq!("\\u2028", encoder.encode("\u{2028}"));
     }
     #[test]
     fn t_java_script_block_ascii_only() {
@@ -153,6 +191,7 @@ mod test {
         assert_eq!("\\x26", encoder.encode("&"));
         generic_tests(&encoder);
         ascii_only_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Block, true);
     }
 
     #[test]
@@ -164,6 +203,7 @@ mod test {
         assert_eq!("\\/", encoder.encode("/"));
         generic_tests(&encoder);
         ascii_extended_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Block, false);
     }
 
     #[test]
@@ -174,6 +214,7 @@ mod test {
         assert_eq!("/", encoder.encode("/"));
         generic_tests(&encoder);
         ascii_only_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Source, true);
     }
 
     #[test]
@@ -184,6 +225,7 @@ mod test {
         assert_eq!("/", encoder.encode("/"));
         generic_tests(&encoder);
         ascii_extended_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Source, false);
     }
 
     #[test]
@@ -196,6 +238,7 @@ mod test {
         assert_eq!("\\x26", encoder.encode("&"));
         generic_tests(&encoder);
         ascii_only_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Html, true);
     }
 
     #[test]
@@ -208,6 +251,7 @@ mod test {
         assert_eq!("\\x26", encoder.encode("&"));
         generic_tests(&encoder);
         ascii_extended_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Html, false);
     }
 
     #[test]
@@ -219,6 +263,7 @@ mod test {
         assert_eq!("\\x26", encoder.encode("&"));
         generic_tests(&encoder);
         ascii_only_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Attribute, true);
     }
 
     #[test]
@@ -230,6 +275,6 @@ mod test {
         assert_eq!("\\x26", encoder.encode("&"));
         generic_tests(&encoder);
         ascii_extended_tests(&encoder);
+        code_point_escape_tests(JavaScriptEncoderMode::Attribute, false);
     }
-
 }
@@ -1,7 +1,19 @@
-
 pub mod common;
+pub mod css_encoder;
+pub mod css_sanitizer;
+mod css_tokenizer;
+pub mod encoder;
+pub mod encoding_reader;
+pub mod encoding_writer;
+pub mod html_decoder;
+pub mod html_encoder;
+mod html_entities;
+pub mod html_sanitizer;
+mod html_tokenizer;
 pub mod uri_encoder;
+pub mod uri_validator;
 pub mod java_script_encoder;
+pub mod xml_decoder;
 pub mod xml_encoder;
-
-
+pub mod xml_name_encoder;
+pub mod xml_writer;
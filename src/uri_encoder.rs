@@ -1,4 +1,6 @@
 use crate::common::{HEX_MASK, HEX_SHIFT, U_HEX, char_bucket, char_mask};
+use crate::encoder::Encoder;
+use std::fmt::{self, Write};
 
 /// 0111_1111_1111 --> highest 2x utf 8 bytes
 /// 0000_1000_0000 --> most sig. utf8 byte
@@ -16,13 +18,27 @@ const UTF8_4_BYTE_FIRST_MSB: u32 = 0b_0000_1111_0000;
 const UTF8_SHIFT: u32 = 0b_0000_0000_0110;
 const UTF8_MASK: u32 = 0b_0000_0011_1111;
 
+/// Writes a single byte as a `%HH` escape using upper-case hex digits.
+#[inline]
+fn encode_as_percent_byte<W: Write + ?Sized>(output: &mut W, byte: u32) -> fmt::Result {
+    output.write_char('%')?;
+    output.write_char(U_HEX[(byte >> HEX_SHIFT) as usize])?;
+    output.write_char(U_HEX[(byte & HEX_MASK) as usize])
+}
+
 pub enum UriEncoderMode {
     Component,
     FullUri,
+    /// Like [`UriEncoderMode::FullUri`], but leaves well-formed `%HH` escapes alone instead of
+    /// encoding their `%`, so URLs that are already encoded can be normalized again without
+    /// turning `%20` into `%2520`. The hex digits of existing escapes are uppercased and a `%`
+    /// that doesn't start an escape is encoded as `%25`.
+    Normalize,
 }
 
 pub struct UriEncoder {
     valid_masks: [u32; 4],
+    preserve_escapes: bool,
 }
 impl UriEncoder {
     pub fn new(mode: UriEncoderMode) -> Self {
@@ -47,9 +63,12 @@ impl UriEncoder {
                     uri_unreserved_bucket2,
                     uri_unreserved_bucket3,
                 ];
-                Self { valid_masks }
+                Self {
+                    valid_masks,
+                    preserve_escapes: false,
+                }
             }
-            UriEncoderMode::FullUri => {
+            UriEncoderMode::FullUri | UriEncoderMode::Normalize => {
                 let reserved_chars1 = [
                     '!', '#', '$', '?', '&', '(', ')', '*', '+', ',', ':', ';', '=', '/', '\'',
                 ];
@@ -70,82 +89,129 @@ impl UriEncoder {
                     uri_unreserved_bucket2 | uri_reserved_bucket2,
                     uri_unreserved_bucket3,
                 ];
-                Self { valid_masks }
+                Self {
+                    valid_masks,
+                    preserve_escapes: matches!(mode, UriEncoderMode::Normalize),
+                }
             }
         }
     }
 
     pub fn encode(&self, input: &str) -> String {
-        let starting_capacity = (u32::MAX / 2u32).min((input.len() * 9usize) as u32) as usize;
-        let mut result = String::with_capacity(starting_capacity);
-        for c in input.chars() {
-            if c as u32 <= 127u32 {
-                let bucket = char_bucket(c);
-                let mask = char_mask(c);
-
-                if (self.valid_masks[bucket] & mask) != 0 {
-                    result.push(c);
-                    continue;
-                } else {
-                    result.push('%');
-                    result.push(U_HEX[(c as u32 >> HEX_SHIFT) as usize]);
-                    result.push(U_HEX[(c as u32 & HEX_MASK) as usize]);
-                    continue;
-                }
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Returns `true` if `c` cannot be written to the output as-is.
+    #[inline]
+    fn requires_encoding(&self, c: char) -> bool {
+        c as u32 > 127u32 || (self.valid_masks[char_bucket(c)] & char_mask(c)) == 0
+    }
+
+    /// Returns the hex digits at the start of `rest`, which follows a `%`, if they make it an
+    /// escape that is kept.
+    #[inline]
+    fn preserved_escape<'a>(&self, rest: &'a str) -> Option<&'a str> {
+        if !self.preserve_escapes {
+            return None;
+        }
+        rest.get(..2)
+            .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+    }
+
+    /// Writes the encoded form of `input` to `output`.
+    ///
+    /// Runs of characters that don't need encoding are written with a single `write_str` call.
+    fn write_encoded<W: Write + ?Sized>(&self, input: &str, output: &mut W) -> fmt::Result {
+        let mut safe_start = 0;
+        for (index, c) in input.char_indices() {
+            // Skips the digits of an escape that was just written.
+            if index < safe_start || !self.requires_encoding(c) {
+                continue;
+            }
+            output.write_str(&input[safe_start..index])?;
+            safe_start = index + c.len_utf8();
+            if let Some(digits) = (c == '%')
+                .then(|| self.preserved_escape(&input[safe_start..]))
+                .flatten()
+            {
+                output.write_char('%')?;
+                output.write_str(&digits.to_ascii_uppercase())?;
+                safe_start += 2;
+            } else if c as u32 <= 127u32 {
+                encode_as_percent_byte(output, c as u32)?;
             } else if c as u32 <= MAX_UTF8_2_BYTE {
-                let b1 = UTF8_2_BYTE_FIRST_MSB | (c as u32 >> UTF8_SHIFT);
-                result.push('%');
-                result.push(U_HEX[(b1 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b1 & HEX_MASK) as usize]);
-
-                let b2 = UTF8_BYTE_MSB | (c as u32 & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b2 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b2 & HEX_MASK) as usize]);
+                encode_as_percent_byte(output, UTF8_2_BYTE_FIRST_MSB | (c as u32 >> UTF8_SHIFT))?;
+                encode_as_percent_byte(output, UTF8_BYTE_MSB | (c as u32 & UTF8_MASK))?;
             } else if c as u32 <= 0xFFFF {
-                let b1 = UTF8_3_BYTE_FIRST_MSB | (c as u32 >> (2 * UTF8_SHIFT));
-                result.push('%');
-                result.push(U_HEX[(b1 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b1 & HEX_MASK) as usize]);
-
-                let b2 = UTF8_BYTE_MSB | ((c as u32 >> UTF8_SHIFT) & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b2 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b2 & HEX_MASK) as usize]);
-
-                let b3 = UTF8_BYTE_MSB | (c as u32 & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b3 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b3 & HEX_MASK) as usize]);
+                encode_as_percent_byte(
+                    output,
+                    UTF8_3_BYTE_FIRST_MSB | (c as u32 >> (2 * UTF8_SHIFT)),
+                )?;
+                encode_as_percent_byte(
+                    output,
+                    UTF8_BYTE_MSB | ((c as u32 >> UTF8_SHIFT) & UTF8_MASK),
+                )?;
+                encode_as_percent_byte(output, UTF8_BYTE_MSB | (c as u32 & UTF8_MASK))?;
             } else {
-                let b1 = UTF8_4_BYTE_FIRST_MSB | (c as u32 >> (3 * UTF8_SHIFT));
-                result.push('%');
-                result.push(U_HEX[(b1 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b1 & HEX_MASK) as usize]);
-
-                let b2 = UTF8_BYTE_MSB | ((c as u32 >> (2 * UTF8_SHIFT)) & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b2 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b2 & HEX_MASK) as usize]);
-
-                let b3 = UTF8_BYTE_MSB | ((c as u32 >> UTF8_SHIFT) & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b3 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b3 & HEX_MASK) as usize]);
-
-                let b4 = UTF8_BYTE_MSB | (c as u32 & UTF8_MASK);
-                result.push('%');
-                result.push(U_HEX[(b4 >> HEX_SHIFT) as usize]);
-                result.push(U_HEX[(b4 & HEX_MASK) as usize]);
+                encode_as_percent_byte(
+                    output,
+                    UTF8_4_BYTE_FIRST_MSB | (c as u32 >> (3 * UTF8_SHIFT)),
+                )?;
+                encode_as_percent_byte(
+                    output,
+                    UTF8_BYTE_MSB | ((c as u32 >> (2 * UTF8_SHIFT)) & UTF8_MASK),
+                )?;
+                encode_as_percent_byte(
+                    output,
+                    UTF8_BYTE_MSB | ((c as u32 >> UTF8_SHIFT) & UTF8_MASK),
+                )?;
+                encode_as_percent_byte(output, UTF8_BYTE_MSB | (c as u32 & UTF8_MASK))?;
             }
         }
-        result.shrink_to_fit();
-        result
+        output.write_str(&input[safe_start..])
+    }
+}
+
+impl Encoder for UriEncoder {
+    fn encode_into(&self, input: &str, output: &mut String) {
+        // Writing to a `String` never fails.
+        let _ = self.write_encoded(input, output);
+    }
+
+    fn encode_fmt(&self, input: &str, output: &mut dyn Write) -> fmt::Result {
+        self.write_encoded(input, output)
+    }
+
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        input
+            .char_indices()
+            .find(|&(index, c)| {
+                self.requires_encoding(c)
+                    // An escape that is already uppercase is written as-is.
+                    && !(c == '%'
+                        && self
+                            .preserved_escape(&input[index + 1..])
+                            .is_some_and(|digits| !digits.bytes().any(|b| b.is_ascii_lowercase())))
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Holds back a trailing `%` or `%H` that may turn out to start an escape.
+    fn pending_suffix_len(&self, input: &str) -> usize {
+        if !self.preserve_escapes {
+            return 0;
+        }
+        match input.as_bytes() {
+            [.., b'%'] => 1,
+            [.., b'%', digit] if digit.is_ascii_hexdigit() => 2,
+            _ => 0,
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::encoder::Encoder;
     use crate::uri_encoder::{UriEncoder, UriEncoderMode};
 
     fn shared_test_cases(encoder: &UriEncoder) {
@@ -189,6 +255,41 @@ mod test {
         shared_test_cases(&encoder);
     }
 
+    #[test]
+    fn test_normalize_encode() {
+        let encoder = UriEncoder::new(UriEncoderMode::Normalize);
+        assert_eq!("a%20b", encoder.encode("a%20b"));
+        assert_eq!("a%20b%20c", encoder.encode("a b%20c"));
+        assert_eq!("%2F%C3%A9%AB", encoder.encode("%2f%c3%a9%aB"));
+        assert_eq!("100%25", encoder.encode("100%"));
+        assert_eq!("%25%252", encoder.encode("%%2"));
+        assert_eq!("%25zz%25a%25%25", encoder.encode("%zz%a%%"));
+        assert_eq!("%25%C3%A9", encoder.encode("%\u{e9}"));
+        assert_eq!("%2520", encoder.encode("%2520"));
+        assert_eq!(
+            "http://www.owasp.org/index.php?foo=bar&baz#fragment",
+            encoder.encode("http://www.owasp.org/index.php?foo=bar&baz#fragment")
+        );
+        assert_eq!("%3C%5E%7B", encoder.encode("<^{"));
+    }
+
+    #[test]
+    fn test_normalize_cow() {
+        let encoder = UriEncoder::new(UriEncoderMode::Normalize);
+        assert!(!encoder.needs_encoding("a%20b%C3%A9"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a%2fb"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a%2"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a%"));
+        assert_eq!(1, encoder.pending_suffix_len("a%"));
+        assert_eq!(2, encoder.pending_suffix_len("a%f"));
+        assert_eq!(0, encoder.pending_suffix_len("a%fg"));
+        assert_eq!(0, encoder.pending_suffix_len("a%20"));
+        assert_eq!(
+            0,
+            UriEncoder::new(UriEncoderMode::FullUri).pending_suffix_len("a%")
+        );
+    }
+
     #[test]
     fn test_full_uri_encode() {
         let encoder = UriEncoder::new(UriEncoderMode::FullUri);
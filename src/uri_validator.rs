@@ -0,0 +1,274 @@
+use crate::uri_encoder::{UriEncoder, UriEncoderMode};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Why a [`UriValidator`] rejected a URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UriValidationError {
+    /// The URL has a scheme that isn't on the allowlist, such as `javascript`. Holds the
+    /// lowercased scheme.
+    DisallowedScheme(String),
+    /// The URL is relative, and relative URLs aren't allowed.
+    RelativeUrl,
+    /// The URL has a `:` before its first `/`, `?` or `#` that doesn't follow a valid scheme,
+    /// such as `1javascript:`, which parsers may read differently.
+    AmbiguousScheme,
+}
+
+impl fmt::Display for UriValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UriValidationError::DisallowedScheme(scheme) => {
+                write!(f, "URL scheme `{}` is not allowed", scheme)
+            }
+            UriValidationError::RelativeUrl => f.write_str("relative URLs are not allowed"),
+            UriValidationError::AmbiguousScheme => f.write_str("URL has an ambiguous scheme"),
+        }
+    }
+}
+
+impl std::error::Error for UriValidationError {}
+
+/// Checks URLs for `href`, `src` and similar attributes against a scheme allowlist before they
+/// are encoded.
+///
+/// [`UriEncoderMode::FullUri`] leaves every character of `javascript:alert(1)` alone, since
+/// they're all valid in a URL, so the scheme has to be checked first. The validator reads the
+/// scheme the way browsers do: leading and trailing whitespace and control characters are
+/// ignored, as are tabs and newlines anywhere in the URL, and the scheme is matched
+/// case-insensitively. `java\tscript:` and ` JavaScript:` are both `javascript:` URLs.
+///
+/// By default `http`, `https` and `mailto` URLs are allowed, as well as relative URLs.
+///
+/// # Example
+/// ```
+/// use tiny_clean::uri_validator::{UriValidationError, UriValidator};
+///
+/// let validator = UriValidator::new();
+/// assert_eq!(
+///     Ok("https://example.com/a%20b".to_string()),
+///     validator.validate(" https://example.com/a b")
+/// );
+/// assert_eq!(
+///     Err(UriValidationError::DisallowedScheme("javascript".to_string())),
+///     validator.validate("java\tscript:alert(1)")
+/// );
+/// assert_eq!("about:invalid", validator.sanitize("javascript:alert(1)"));
+/// ```
+pub struct UriValidator {
+    schemes: HashSet<String>,
+    relative: bool,
+    placeholder: String,
+    encoder: UriEncoder,
+}
+
+impl Default for UriValidator {
+    fn default() -> Self {
+        Self {
+            schemes: ["http", "https", "mailto"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            relative: true,
+            placeholder: "about:invalid".to_string(),
+            encoder: UriEncoder::new(UriEncoderMode::FullUri),
+        }
+    }
+}
+
+impl UriValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the schemes allowed for absolute URLs, replacing the default of `http`, `https`
+    /// and `mailto`.
+    pub fn with_allowed_schemes(mut self, schemes: &[&str]) -> Self {
+        self.schemes = schemes.iter().map(|s| s.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Sets whether relative URLs such as `/path`, `page.html` and `//example.com` are allowed.
+    /// Defaults to `true`.
+    pub fn with_relative_urls(mut self, allowed: bool) -> Self {
+        self.relative = allowed;
+        self
+    }
+
+    /// Sets what [`UriValidator::sanitize`] returns for a rejected URL. Defaults to
+    /// `about:invalid`, which browsers don't navigate to.
+    pub fn with_placeholder(mut self, placeholder: &str) -> Self {
+        self.placeholder = placeholder.to_string();
+        self
+    }
+
+    /// Returns a validator that allows relative URLs and URLs with one of `schemes`, for the
+    /// sanitizers' policies, which lowercase their schemes already.
+    pub(crate) fn from_schemes(schemes: &HashSet<String>) -> Self {
+        Self {
+            schemes: schemes.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns `true` if `url` is relative or uses an allowed scheme.
+    pub fn is_allowed(&self, url: &str) -> bool {
+        self.check(&strip(url)).is_ok()
+    }
+
+    /// Checks `url` and returns it encoded with [`UriEncoderMode::FullUri`], without the
+    /// whitespace and control characters that browsers ignore.
+    pub fn validate(&self, url: &str) -> Result<String, UriValidationError> {
+        let url = strip(url);
+        self.check(&url)?;
+        Ok(self.encoder.encode(&url))
+    }
+
+    /// Like [`UriValidator::validate`], but returns the placeholder for a rejected URL.
+    pub fn sanitize(&self, url: &str) -> String {
+        self.validate(url)
+            .unwrap_or_else(|_| self.placeholder.clone())
+    }
+
+    /// Checks a URL that has been stripped already.
+    fn check(&self, url: &str) -> Result<(), UriValidationError> {
+        match scheme(url)? {
+            Some(scheme) if self.schemes.contains(&scheme) => Ok(()),
+            Some(scheme) => Err(UriValidationError::DisallowedScheme(scheme)),
+            None if self.relative => Ok(()),
+            None => Err(UriValidationError::RelativeUrl),
+        }
+    }
+}
+
+/// Removes what browsers ignore when parsing a URL: leading and trailing C0 controls and
+/// spaces, and tabs and newlines anywhere.
+fn strip(url: &str) -> Cow<'_, str> {
+    let url = url.trim_matches(|c: char| c <= ' ');
+    if url.contains(['\t', '\n', '\r']) {
+        Cow::Owned(url.replace(['\t', '\n', '\r'], ""))
+    } else {
+        Cow::Borrowed(url)
+    }
+}
+
+/// Returns the lowercased scheme of `url`, or `None` if it's relative.
+fn scheme(url: &str) -> Result<Option<String>, UriValidationError> {
+    let scheme_end = url.find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)));
+    match scheme_end {
+        Some(end) if end > 0 && url[end..].starts_with(':') => {
+            if !url.as_bytes()[0].is_ascii_alphabetic() {
+                return Err(UriValidationError::AmbiguousScheme);
+            }
+            Ok(Some(url[..end].to_ascii_lowercase()))
+        }
+        _ => {
+            let first_segment = url.split(['/', '?', '#']).next().unwrap_or_default();
+            if first_segment.contains(':') {
+                return Err(UriValidationError::AmbiguousScheme);
+            }
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::uri_validator::{UriValidationError, UriValidator};
+
+    #[test]
+    fn test_dangerous_schemes() {
+        let validator = UriValidator::new();
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " javascript:alert(1)",
+            "\u{1}javascript:alert(1)",
+            "java\tscript:alert(1)",
+            "java\nscript:alert(1)",
+            "javascript\r:alert(1)",
+        ] {
+            assert_eq!(
+                Err(UriValidationError::DisallowedScheme("javascript".into())),
+                validator.validate(url),
+                "{url:?}"
+            );
+        }
+        assert!(!validator.is_allowed("vbscript:msgbox(1)"));
+        assert!(!validator.is_allowed("data:text/html,<script>alert(1)</script>"));
+        assert_eq!(
+            Err(UriValidationError::AmbiguousScheme),
+            validator.validate("1javascript:alert(1)")
+        );
+        assert_eq!(
+            Err(UriValidationError::AmbiguousScheme),
+            validator.validate("java script:alert(1)")
+        );
+        assert_eq!(
+            Err(UriValidationError::AmbiguousScheme),
+            validator.validate(":alert(1)")
+        );
+    }
+
+    #[test]
+    fn test_allowed_urls() {
+        let validator = UriValidator::new();
+        assert_eq!(
+            Ok("https://example.com/a?b=c&d=e#f".to_string()),
+            validator.validate("https://example.com/a?b=c&d=e#f")
+        );
+        assert_eq!(
+            Ok("HTTP://example.com/caf%C3%A9%20%3Cx%3E".to_string()),
+            validator.validate("\n HTTP://example.com/caf\u{e9} <x>\u{0}")
+        );
+        assert_eq!(
+            Ok("mailto:a@example.com".to_string()),
+            validator.validate("mailto:a@example.com")
+        );
+        for url in [
+            "/relative/path:with-colon",
+            "page.html#a:b",
+            "?q=a:b",
+            "//example.com",
+            "",
+        ] {
+            assert!(validator.is_allowed(url), "{url:?}");
+        }
+    }
+
+    #[test]
+    fn test_configuration() {
+        let validator = UriValidator::new()
+            .with_allowed_schemes(&["FTP"])
+            .with_relative_urls(false)
+            .with_placeholder("#");
+        assert!(validator.is_allowed("ftp://example.com"));
+        assert_eq!(
+            Err(UriValidationError::DisallowedScheme("https".into())),
+            validator.validate("https://example.com")
+        );
+        assert_eq!(
+            Err(UriValidationError::RelativeUrl),
+            validator.validate("/a")
+        );
+        assert_eq!("#", validator.sanitize("/a"));
+        assert_eq!("ftp://a/%20b", validator.sanitize("ftp://a/ b "));
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(
+            "URL scheme `javascript` is not allowed",
+            UriValidationError::DisallowedScheme("javascript".into()).to_string()
+        );
+        assert_eq!(
+            "relative URLs are not allowed",
+            UriValidationError::RelativeUrl.to_string()
+        );
+        assert_eq!(
+            "URL has an ambiguous scheme",
+            UriValidationError::AmbiguousScheme.to_string()
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use crate::xml_encoder::XmlVersion;
+use crate::xml_name_encoder::{is_xml_name_char, is_xml_name_start_char};
+use std::borrow::Cow;
+use std::fmt;
+
+/// What went wrong while decoding a reference, see [`XmlDecodeError::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XmlDecodeErrorKind {
+    /// An `&` that doesn't start a well-formed `&name;` or `&#...;` reference.
+    MalformedReference,
+    /// A well-formed entity reference other than the five predefined ones.
+    UnknownEntity,
+    /// A numeric reference to a code point that is not a valid character in the XML version.
+    InvalidCharReference,
+}
+
+/// Returned by [`XmlDecoder::decode`] in strict mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XmlDecodeError {
+    offset: usize,
+    kind: XmlDecodeErrorKind,
+}
+
+impl XmlDecodeError {
+    /// The byte offset of the `&` that starts the offending reference.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn kind(&self) -> XmlDecodeErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for XmlDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self.kind {
+            XmlDecodeErrorKind::MalformedReference => "malformed reference",
+            XmlDecodeErrorKind::UnknownEntity => "unknown entity",
+            XmlDecodeErrorKind::InvalidCharReference => "invalid character reference",
+        };
+        write!(f, "{} at byte offset {}", description, self.offset)
+    }
+}
+
+impl std::error::Error for XmlDecodeError {}
+
+/// Decodes the predefined entities (`&amp;`, `&lt;`, `&gt;`, `&apos;`, `&quot;`) and numeric
+/// character references (`&#233;`, `&#xe9;`), reversing what
+/// [`XmlEncoder`](crate::xml_encoder::XmlEncoder) produces.
+///
+/// By default the decoder is lenient: anything that isn't a valid reference is passed through
+/// as-is. In strict mode it's reported as an [`XmlDecodeError`] instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XmlDecoder {
+    strict: bool,
+    version: XmlVersion,
+}
+
+impl XmlDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails on unknown entities, a bare `&` and references to invalid characters instead of
+    /// passing them through.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets the XML version whose `Char` production numeric references are checked against.
+    /// Defaults to [`XmlVersion::V1_0`]; XML 1.1 also allows references to control characters.
+    pub fn with_version(mut self, version: XmlVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Decodes every reference in `input`, borrowing it if there's nothing to decode.
+    ///
+    /// Only fails in strict mode.
+    pub fn decode<'a>(&self, input: &'a str) -> Result<Cow<'a, str>, XmlDecodeError> {
+        let mut output = String::new();
+        let mut copied = 0;
+        let mut search = 0;
+        while let Some(found) = input[search..].find('&') {
+            let start = search + found;
+            match self.parse_reference(&input[start..]) {
+                Ok((c, len)) => {
+                    if copied == 0 {
+                        output.reserve(input.len());
+                    }
+                    output.push_str(&input[copied..start]);
+                    output.push(c);
+                    copied = start + len;
+                    search = copied;
+                }
+                Err(kind) if self.strict => {
+                    return Err(XmlDecodeError {
+                        offset: start,
+                        kind,
+                    });
+                }
+                Err(_) => search = start + 1,
+            }
+        }
+        if copied == 0 {
+            return Ok(Cow::Borrowed(input));
+        }
+        output.push_str(&input[copied..]);
+        Ok(Cow::Owned(output))
+    }
+
+    /// Parses the reference at the start of `input`, which starts with `&`, and returns the
+    /// character it stands for and its length.
+    fn parse_reference(&self, input: &str) -> Result<(char, usize), XmlDecodeErrorKind> {
+        let rest = &input[1..];
+        let body_len = match rest.strip_prefix('#') {
+            Some(number) => 1 + number.bytes().take_while(u8::is_ascii_alphanumeric).count(),
+            None if rest.starts_with(is_xml_name_start_char) => {
+                rest.find(|c| !is_xml_name_char(c)).unwrap_or(rest.len())
+            }
+            None => 0,
+        };
+        if body_len == 0 || rest.as_bytes().get(body_len) != Some(&b';') {
+            return Err(XmlDecodeErrorKind::MalformedReference);
+        }
+        let body = &rest[..body_len];
+        let c = match body {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "apos" => '\'',
+            "quot" => '"',
+            _ => match body.strip_prefix('#') {
+                Some(number) => self.parse_char_reference(number)?,
+                None => return Err(XmlDecodeErrorKind::UnknownEntity),
+            },
+        };
+        Ok((c, body_len + 2))
+    }
+
+    /// Parses the part of a numeric reference between `&#` and `;`.
+    fn parse_char_reference(&self, number: &str) -> Result<char, XmlDecodeErrorKind> {
+        let (digits, radix) = match number.strip_prefix('x') {
+            Some(hex) => (hex, 16),
+            None => (number, 10),
+        };
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(XmlDecodeErrorKind::MalformedReference);
+        }
+        u32::from_str_radix(digits, radix)
+            .ok()
+            .and_then(char::from_u32)
+            .filter(|&c| self.is_xml_char(c))
+            .ok_or(XmlDecodeErrorKind::InvalidCharReference)
+    }
+
+    /// Returns `true` if `c` matches the `Char` production of the XML version.
+    #[inline]
+    fn is_xml_char(&self, c: char) -> bool {
+        match c {
+            '\t' | '\n' | '\r' => true,
+            '\0' => false,
+            _ if c < ' ' => self.version == XmlVersion::V1_1,
+            _ => c != '\u{fffe}' && c != '\u{ffff}',
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xml_decoder::{XmlDecodeErrorKind, XmlDecoder};
+    use crate::xml_encoder::{EntityStyle, XmlEncoder, XmlEncoderMode, XmlVersion};
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_decode() {
+        let decoder = XmlDecoder::new();
+        assert_eq!(
+            "&<>'\"",
+            decoder.decode("&amp;&lt;&gt;&apos;&quot;").unwrap()
+        );
+        assert_eq!("a\u{e9}b", decoder.decode("a&#233;b").unwrap());
+        assert_eq!("a\u{e9}b", decoder.decode("a&#xe9;b").unwrap());
+        assert_eq!("\u{e9}", decoder.decode("&#x00E9;").unwrap());
+        assert_eq!("\u{1f600}", decoder.decode("&#x1f600;").unwrap());
+        assert_eq!("\t\n\r", decoder.decode("&#9;&#10;&#13;").unwrap());
+        assert_eq!("&lt;", decoder.decode("&amp;lt;").unwrap());
+    }
+
+    #[test]
+    fn test_lenient_pass_through() {
+        let decoder = XmlDecoder::new();
+        for input in [
+            "a & b",
+            "&",
+            "&amp",
+            "&nbsp;",
+            "&#;",
+            "&#x;",
+            "&#X41;",
+            "&#12a;",
+            "&#0;",
+            "&#x1;",
+            "&#xd800;",
+            "&#xfffe;",
+            "&#x110000;",
+            "&#99999999999;",
+        ] {
+            assert_eq!(input, decoder.decode(input).unwrap());
+        }
+        assert_eq!("& <", decoder.decode("& &lt;").unwrap());
+        assert_eq!("&nbsp;<", decoder.decode("&nbsp;&lt;").unwrap());
+    }
+
+    #[test]
+    fn test_strict_errors() {
+        let decoder = XmlDecoder::new().with_strict(true);
+        assert_eq!("a<b", decoder.decode("a&lt;b").unwrap());
+        let cases = [
+            ("a & b", 2, XmlDecodeErrorKind::MalformedReference),
+            ("&lt;&amp", 4, XmlDecodeErrorKind::MalformedReference),
+            ("&#;", 0, XmlDecodeErrorKind::MalformedReference),
+            ("x&nbsp;", 1, XmlDecodeErrorKind::UnknownEntity),
+            ("&foo-bar;", 0, XmlDecodeErrorKind::UnknownEntity),
+            ("&x:y_z.1\u{e9};", 0, XmlDecodeErrorKind::UnknownEntity),
+            ("&a#b;", 0, XmlDecodeErrorKind::MalformedReference),
+            ("&1a;", 0, XmlDecodeErrorKind::MalformedReference),
+            ("&-a;", 0, XmlDecodeErrorKind::MalformedReference),
+            ("&##1;", 0, XmlDecodeErrorKind::MalformedReference),
+            ("&#0;", 0, XmlDecodeErrorKind::InvalidCharReference),
+            ("&#x1b;", 0, XmlDecodeErrorKind::InvalidCharReference),
+            ("&#xD800;", 0, XmlDecodeErrorKind::InvalidCharReference),
+            ("&#x110000;", 0, XmlDecodeErrorKind::InvalidCharReference),
+        ];
+        for (input, offset, kind) in cases {
+            let error = decoder.decode(input).unwrap_err();
+            assert_eq!(offset, error.offset(), "{input}");
+            assert_eq!(kind, error.kind(), "{input}");
+        }
+        assert_eq!(
+            "unknown entity at byte offset 1",
+            decoder.decode("x&nbsp;").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_xml_1_1() {
+        let decoder = XmlDecoder::new()
+            .with_strict(true)
+            .with_version(XmlVersion::V1_1);
+        assert_eq!("\u{1}\u{85}", decoder.decode("&#x1;&#x85;").unwrap());
+        assert!(decoder.decode("&#0;").is_err());
+    }
+
+    #[test]
+    fn test_cow() {
+        let decoder = XmlDecoder::new();
+        assert!(matches!(decoder.decode("plain"), Ok(Cow::Borrowed(_))));
+        assert!(matches!(decoder.decode("a & b"), Ok(Cow::Borrowed(_))));
+        assert!(matches!(decoder.decode("a &amp; b"), Ok(Cow::Owned(_))));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let input = "<a href=\"x\">caf\u{e9} & 'tea'\t\n\r\u{1f600}</a>";
+        let decoder = XmlDecoder::new().with_strict(true);
+        for mode in [
+            XmlEncoderMode::All,
+            XmlEncoderMode::Content,
+            XmlEncoderMode::Attribute,
+            XmlEncoderMode::SingleQuotedAttribute,
+            XmlEncoderMode::DoubleQuotedAttribute,
+        ] {
+            for style in [
+                EntityStyle::Mixed,
+                EntityStyle::Named,
+                EntityStyle::Decimal,
+                EntityStyle::Hex,
+            ] {
+                let encoder = XmlEncoder::new(mode)
+                    .with_entity_style(style)
+                    .with_ascii_only(true)
+                    .with_attribute_whitespace_references(true);
+                let encoded = encoder.encode(input);
+                assert_eq!(input, decoder.decode(&encoded).unwrap());
+            }
+        }
+    }
+}
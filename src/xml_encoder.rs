@@ -1,159 +1,566 @@
-use crate::common::{char_bucket, char_mask};
+use crate::common::{char_bucket, char_mask, encode_as_hex_digits};
+use crate::encoder::Encoder;
+use std::fmt::{self, Write};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum XmlEncoderMode {
     All,
     Content,
     Attribute,
     SingleQuotedAttribute,
     DoubleQuotedAttribute,
+    /// Text placed between `<![CDATA[` and `]]>`.
+    ///
+    /// Everything is passed through except `]]>`, which is split across two CDATA sections
+    /// (`]]]]><![CDATA[>`). Characters that can only be written as references, such as non-ASCII
+    /// characters with `ascii_only` set, close the section, write the reference and open a new one.
+    CData,
+    /// Text placed between `<!--` and `-->`.
+    ///
+    /// Comments can't contain `--` or end with `-`, so a space is written between consecutive
+    /// hyphens and after a trailing one. References are not recognized inside comments, so
+    /// everything else is passed through; characters that would need a reference, such as
+    /// non-ASCII characters with `ascii_only` set, are written as the literal reference text.
+    Comment,
 }
 
+/// What [`XmlEncoder`] does with characters that are not allowed in an XML document, such as C0
+/// control characters other than tab, CR and LF, or the noncharacters `U+FFFE` and `U+FFFF`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidCharPolicy {
+    /// Drop the character from the output.
+    Remove,
+    /// Write the given character instead, e.g. `' '` (the default) or
+    /// `char::REPLACEMENT_CHARACTER`.
+    Replace(char),
+    /// Make [`XmlEncoder::try_encode`] fail with an [`InvalidCharError`].
+    ///
+    /// Methods that can't report the error, such as [`XmlEncoder::encode`] and the [`Encoder`]
+    /// trait methods, write `U+FFFD` instead.
+    Error,
+}
+
+impl Default for InvalidCharPolicy {
+    fn default() -> Self {
+        InvalidCharPolicy::Replace(' ')
+    }
+}
+
+/// The XML version whose character rules [`XmlEncoder`] follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XmlVersion {
+    /// Control characters other than tab, LF and CR are not allowed and are handled by the
+    /// [`InvalidCharPolicy`].
+    #[default]
+    V1_0,
+    /// The restricted characters (`U+0001`-`U+001F` except whitespace, `U+007F`-`U+009F`) are
+    /// written as references, as XML 1.1 requires. NEL (`U+0085`) and LSEP (`U+2028`) are written
+    /// as references too, since a parser would turn them into line feeds. `U+0000` is still
+    /// invalid.
+    V1_1,
+}
+
+/// How [`XmlEncoder`] writes character references.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EntityStyle {
+    /// `&amp;`, `&lt;` and `&gt;` by name, quotes and whitespace as decimal references
+    /// (`&#39;`, `&#34;`, `&#10;`) and everything else as hexadecimal references (`&#xe9;`).
+    #[default]
+    Mixed,
+    /// The five predefined XML entities by name (`&amp;`, `&lt;`, `&gt;`, `&apos;`, `&quot;`)
+    /// and everything else as hexadecimal references.
+    Named,
+    /// Decimal references only, e.g. `&#38;` and `&#233;`.
+    Decimal,
+    /// Hexadecimal references only, e.g. `&#x26;` and `&#xe9;`.
+    Hex,
+}
+
+/// Returned by [`XmlEncoder::try_encode`] when the input contains a character that is not
+/// allowed in XML and the policy is [`InvalidCharPolicy::Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidCharError {
+    offset: usize,
+    character: char,
+}
+
+impl InvalidCharError {
+    /// The byte offset of the invalid character in the input.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The invalid character.
+    pub fn character(&self) -> char {
+        self.character
+    }
+}
+
+impl fmt::Display for InvalidCharError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid XML character U+{:04X} at byte offset {}",
+            self.character as u32, self.offset
+        )
+    }
+}
+
+impl std::error::Error for InvalidCharError {}
+
+/// Internal error type for the shared encoding loop.
+enum WriteError {
+    Fmt,
+    InvalidChar(InvalidCharError),
+}
+
+impl From<fmt::Error> for WriteError {
+    fn from(_: fmt::Error) -> Self {
+        WriteError::Fmt
+    }
+}
+
+/// Tab, LF and CR, the only control characters allowed in XML 1.0.
+const XML_WHITESPACE_MASK: u32 = char_mask('\t') | char_mask('\n') | char_mask('\r');
+
 pub struct XmlEncoder {
+    mode: XmlEncoderMode,
     valid_masks: [u32; 4],
+    strict: bool,
+    ascii_only: bool,
+    invalid_char_policy: InvalidCharPolicy,
+    entity_style: EntityStyle,
+    version: XmlVersion,
 }
 
 impl XmlEncoder {
+    #[allow(clippy::identity_op)]
     pub fn new(mode: XmlEncoderMode) -> Self {
-        let base_mask = char_mask('\r') | char_mask('\t') | char_mask('\n');
-        match mode {
-            XmlEncoderMode::All => {
-                let to_be_encoded = ['&', '<', '>', '\'', '"'];
-                let mut to_be_encoded_mask = 0u32;
-                for char in to_be_encoded {
-                    to_be_encoded_mask |= char_mask(char);
-                }
-                let valid_masks = [
-                    base_mask,
-                    u32::MAX & !to_be_encoded_mask,
-                    u32::MAX,
-                    u32::MAX
-                ];
-                Self {valid_masks}
+        let to_be_encoded: &[char] = match mode {
+            XmlEncoderMode::All => &['&', '<', '>', '\'', '"'],
+            XmlEncoderMode::Content => &['&', '<', '>'],
+            XmlEncoderMode::Attribute => &['&', '<', '\'', '"'],
+            XmlEncoderMode::SingleQuotedAttribute => &['&', '<', '\''],
+            XmlEncoderMode::DoubleQuotedAttribute => &['&', '<', '"'],
+            XmlEncoderMode::CData => &['>'],
+            XmlEncoderMode::Comment => &['-'],
+        };
+        let mut to_be_encoded_mask = 0u32;
+        for char in to_be_encoded {
+            to_be_encoded_mask |= char_mask(*char);
+        }
+        let valid_masks = [
+            XML_WHITESPACE_MASK,
+            u32::MAX & !to_be_encoded_mask,
+            u32::MAX,
+            u32::MAX,
+        ];
+        Self {
+            mode,
+            valid_masks,
+            strict: false,
+            ascii_only: false,
+            invalid_char_policy: InvalidCharPolicy::default(),
+            entity_style: EntityStyle::default(),
+            version: XmlVersion::default(),
+        }
+    }
+
+    /// Writes every non-ASCII character as a hexadecimal numeric character reference
+    /// (`&#xe9;`, `&#x1f600;`), so the output only contains 7-bit characters.
+    pub fn with_ascii_only(mut self, ascii_only: bool) -> Self {
+        self.ascii_only = ascii_only;
+        self
+    }
+
+    /// Writes tab, LF and CR as numeric character references (`&#9;`, `&#10;`, `&#13;`) in
+    /// [`XmlEncoderMode::All`] and the attribute modes.
+    ///
+    /// XML parsers normalize literal whitespace in attribute values to spaces, so without this a
+    /// multi-line value doesn't survive a round trip. Other modes are not affected.
+    pub fn with_attribute_whitespace_references(mut self, enabled: bool) -> Self {
+        let applies = matches!(
+            self.mode,
+            XmlEncoderMode::All
+                | XmlEncoderMode::Attribute
+                | XmlEncoderMode::SingleQuotedAttribute
+                | XmlEncoderMode::DoubleQuotedAttribute
+        );
+        if applies {
+            self.valid_masks[0] = if enabled { 0 } else { XML_WHITESPACE_MASK };
+        }
+        self
+    }
+
+    /// Sets the XML version the output is written for. Defaults to [`XmlVersion::V1_0`].
+    pub fn with_version(mut self, version: XmlVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets how character references are written. Defaults to [`EntityStyle::Mixed`].
+    pub fn with_entity_style(mut self, style: EntityStyle) -> Self {
+        self.entity_style = style;
+        self
+    }
+
+    /// Sets what happens to characters that are not allowed in XML. Defaults to replacing them
+    /// with a space.
+    pub fn with_invalid_char_policy(mut self, policy: InvalidCharPolicy) -> Self {
+        self.invalid_char_policy = policy;
+        self
+    }
+
+    /// Also replaces the Unicode noncharacters `U+FDD0`-`U+FDEF` and the last two code points of
+    /// every plane (`U+xFFFE`, `U+xFFFF`).
+    ///
+    /// These are legal XML 1.0 characters, but the XML specification discourages their use and
+    /// some consumers reject them. `U+FFFE` and `U+FFFF` are not XML characters at all and are
+    /// always replaced.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn encode(&self, input: &str) -> String {
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Encodes `input`, failing on the first invalid character if the policy is
+    /// [`InvalidCharPolicy::Error`].
+    ///
+    /// With any other policy this behaves exactly like [`XmlEncoder::encode`].
+    pub fn try_encode(&self, input: &str) -> Result<String, InvalidCharError> {
+        let mut result = String::with_capacity(input.len());
+        match self.write_encoded(input, &mut result, true) {
+            Err(WriteError::InvalidChar(error)) => Err(error),
+            // Writing to a `String` never fails.
+            _ => Ok(result),
+        }
+    }
+
+    /// Returns `true` if `c` cannot be written to the output as-is.
+    #[inline]
+    fn requires_encoding(&self, c: char) -> bool {
+        if (c as u32) < 127 {
+            c <= '>' && self.valid_masks[char_bucket(c)] & char_mask(c) == 0
+        } else {
+            (self.ascii_only && c as u32 > 127)
+                || (self.version == XmlVersion::V1_1 && (c <= '\u{9f}' || c == '\u{2028}'))
+                || self.is_invalid_char(c)
+        }
+    }
+
+    /// Returns `true` for characters that are not allowed in the output and are handled by the
+    /// [`InvalidCharPolicy`].
+    #[inline]
+    fn is_invalid(&self, c: char) -> bool {
+        if c < ' ' {
+            XML_WHITESPACE_MASK & char_mask(c) == 0
+                && (self.version == XmlVersion::V1_0 || c == '\0')
+        } else {
+            self.is_invalid_char(c)
+        }
+    }
+
+    /// Returns `true` for characters outside of the ASCII range that are replaced by the encoder.
+    ///
+    /// Everything allowed by the XML 1.0 `Char` production is kept, including characters beyond
+    /// the Basic Multilingual Plane, unless `strict` is set.
+    #[inline]
+    fn is_invalid_char(&self, c: char) -> bool {
+        if c <= '\u{fdcf}' {
+            false
+        } else if c == '\u{fffe}' || c == '\u{ffff}' {
+            true
+        } else {
+            self.strict && (c <= '\u{fdef}' || (c as u32 & 0xFFFE) == 0xFFFE)
+        }
+    }
+
+    /// Returns `true` for characters whose encoding depends on the characters around them, like
+    /// the `]` of a `]]>` sequence in [`XmlEncoderMode::CData`] or a `-` in
+    /// [`XmlEncoderMode::Comment`].
+    #[inline]
+    fn is_context_char(&self, c: char) -> bool {
+        match self.mode {
+            XmlEncoderMode::CData => c == ']',
+            XmlEncoderMode::Comment => c == '-',
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the flagged character `c` at `index` can be written as-is, given that
+    /// everything before it was passed through unchanged.
+    #[inline]
+    fn is_harmless_in_context(&self, input: &str, index: usize, c: char) -> bool {
+        match self.mode {
+            XmlEncoderMode::CData => c == '>' && !input[..index].ends_with("]]"),
+            XmlEncoderMode::Comment => {
+                c == '-' && !input[..index].ends_with('-') && index + 1 < input.len()
             }
-            XmlEncoderMode::Content => {
-                let to_be_encoded = ['&', '<', '>'];
-                let mut to_be_encoded_mask = 0u32;
-                for char in to_be_encoded {
-                    to_be_encoded_mask |= char_mask(char);
-                }
-                let valid_masks = [
-                    base_mask,
-                    u32::MAX & !to_be_encoded_mask,
-                    u32::MAX,
-                    u32::MAX
-                ];
-                Self {valid_masks}
+            _ => false,
+        }
+    }
+
+    /// Steps back from `index` over the run of context characters in front of it and returns
+    /// where that run starts.
+    fn context_run_start(&self, input: &str, index: usize) -> usize {
+        input[..index]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| self.is_context_char(c))
+            .last()
+            .map_or(index, |(start, _)| start)
+    }
+
+    /// Writes the encoded form of `input` to `output`.
+    ///
+    /// Runs of characters that don't need encoding are written with a single `write_str` call.
+    /// Invalid characters are only reported as an error if `report_invalid` is set and the
+    /// policy is [`InvalidCharPolicy::Error`]; otherwise they're replaced by `U+FFFD`.
+    fn write_encoded<W: Write + ?Sized>(
+        &self,
+        input: &str,
+        output: &mut W,
+        report_invalid: bool,
+    ) -> Result<(), WriteError> {
+        let output = &mut TrailingChars::new(output);
+        let mut safe_start = 0;
+        for (index, c) in input.char_indices() {
+            if !self.requires_encoding(c) {
+                continue;
             }
-            XmlEncoderMode::Attribute => {
-                let to_be_encoded = ['&', '<', '\'', '"'];
-                let mut to_be_encoded_mask = 0u32;
-                for char in to_be_encoded {
-                    to_be_encoded_mask |= char_mask(char);
+            output.write_str(&input[safe_start..index])?;
+            safe_start = index + c.len_utf8();
+            if !self.is_invalid(c) {
+                self.write_valid_char(c, output)?;
+                continue;
+            }
+            match self.invalid_char_policy {
+                InvalidCharPolicy::Remove => {}
+                InvalidCharPolicy::Replace(replacement) => {
+                    self.write_replacement(replacement, output)?
+                }
+                InvalidCharPolicy::Error if report_invalid => {
+                    return Err(WriteError::InvalidChar(InvalidCharError {
+                        offset: index,
+                        character: c,
+                    }));
+                }
+                InvalidCharPolicy::Error => {
+                    self.write_valid_char(char::REPLACEMENT_CHARACTER, output)?
                 }
-                let valid_masks = [
-                    base_mask,
-                    u32::MAX & !to_be_encoded_mask,
-                    u32::MAX,
-                    u32::MAX
-                ];
-                Self {valid_masks}
             }
-            XmlEncoderMode::SingleQuotedAttribute => {
-                let to_be_encoded = ['&', '<', '\''];
-                let mut to_be_encoded_mask = 0u32;
-                for char in to_be_encoded {
-                    to_be_encoded_mask |= char_mask(char);
+        }
+        output.write_str(&input[safe_start..])?;
+        if self.mode == XmlEncoderMode::Comment && output.ends_with_char('-') {
+            output.write_char(' ')?;
+        }
+        Ok(())
+    }
+
+    /// Writes a character that is allowed in the output, using a reference if needed.
+    #[inline]
+    fn write_valid_char<W: Write + ?Sized>(
+        &self,
+        c: char,
+        output: &mut TrailingChars<W>,
+    ) -> fmt::Result {
+        match c {
+            '>' if self.mode == XmlEncoderMode::CData => {
+                if output.ends_with(']', ']') {
+                    output.write_str("]]><![CDATA[")?;
                 }
-                let valid_masks = [
-                    base_mask,
-                    u32::MAX & !to_be_encoded_mask,
-                    u32::MAX,
-                    u32::MAX
-                ];
-                Self {valid_masks}
+                output.write_char('>')
             }
-            XmlEncoderMode::DoubleQuotedAttribute => {
-                let to_be_encoded = ['&', '<', '"'];
-                let mut to_be_encoded_mask = 0u32;
-                for char in to_be_encoded {
-                    to_be_encoded_mask |= char_mask(char);
+            '-' if self.mode == XmlEncoderMode::Comment => {
+                if output.ends_with_char('-') {
+                    output.write_char(' ')?;
                 }
-                let valid_masks = [
-                    base_mask,
-                    u32::MAX & !to_be_encoded_mask,
-                    u32::MAX,
-                    u32::MAX
-                ];
-                Self {valid_masks}
+                output.write_char('-')
+            }
+            _ if !self.requires_encoding(c) => output.write_char(c),
+            _ if self.mode == XmlEncoderMode::CData => {
+                output.write_str("]]>")?;
+                self.write_reference(c, output)?;
+                output.write_str("<![CDATA[")
             }
+            _ => self.write_reference(c, output),
         }
     }
 
-    pub fn encode(&self, input: &str) -> String {
-        let max_capacity = (u32::MAX / 2).min((input.len() * 5) as u32) as usize;
-        let mut result = String::with_capacity(max_capacity);
-        for c in input.chars() {
-            if (c as u32) < 127 {
-                let bucket = char_bucket(c);
-                let mask = char_mask(c);
-                if c > '>' || self.valid_masks[bucket] & mask != 0 {
-                    result.push(c);
-                } else {
-                    match c {
-                        '&' => {
-                            result.push('&');
-                            result.push('a');
-                            result.push('m');
-                            result.push('p');
-                            result.push(';');
-                        }
-                        '<' => {
-                            result.push('&');
-                            result.push('l');
-                            result.push('t');
-                            result.push(';');
-                        }
-                        '>' => {
-                            result.push('&');
-                            result.push('g');
-                            result.push('t');
-                            result.push(';');
-                        }
-                        '\'' => {
-                            result.push('&');
-                            result.push('#');
-                            result.push('3');
-                            result.push('9');
-                            result.push(';');
-                        }
-                        '\"' => {
-                            result.push('&');
-                            result.push('#');
-                            result.push('3');
-                            result.push('4');
-                            result.push(';');
-                        }
-                        _ => result.push(' '),
-                    }
-                }
-            } else if c > '\u{fffd}' || (c >= '\u{fdd0}' && c <= '\u{fdef}') {
-                result.push(' ');
-            } else {
-                result.push(c);
+    /// Writes `c` as a character reference in the configured [`EntityStyle`].
+    #[inline]
+    fn write_reference<W: Write + ?Sized>(&self, c: char, output: &mut W) -> fmt::Result {
+        match (self.entity_style, c) {
+            (EntityStyle::Mixed | EntityStyle::Named, '&') => output.write_str("&amp;"),
+            (EntityStyle::Mixed | EntityStyle::Named, '<') => output.write_str("&lt;"),
+            (EntityStyle::Mixed | EntityStyle::Named, '>') => output.write_str("&gt;"),
+            (EntityStyle::Named, '\'') => output.write_str("&apos;"),
+            (EntityStyle::Named, '"') => output.write_str("&quot;"),
+            (EntityStyle::Mixed, '\'' | '"' | '\t' | '\n' | '\r') | (EntityStyle::Decimal, _) => {
+                write!(output, "&#{};", c as u32)
             }
+            _ => write_hex_reference(c, output),
+        }
+    }
+
+    /// Writes the character chosen by [`InvalidCharPolicy::Replace`], encoding it if needed.
+    ///
+    /// A replacement that is itself invalid falls back to `U+FFFD`.
+    fn write_replacement<W: Write + ?Sized>(
+        &self,
+        replacement: char,
+        output: &mut TrailingChars<W>,
+    ) -> fmt::Result {
+        if self.is_invalid(replacement) {
+            self.write_valid_char(char::REPLACEMENT_CHARACTER, output)
+        } else {
+            self.write_valid_char(replacement, output)
         }
-        result.shrink_to_fit();
-        result
+    }
+}
+
+/// Wraps the output and remembers the last two characters written to it, which decide how
+/// sequences such as `]]>` have to be broken up.
+struct TrailingChars<'a, W: Write + ?Sized> {
+    inner: &'a mut W,
+    last: [char; 2],
+}
+
+impl<'a, W: Write + ?Sized> TrailingChars<'a, W> {
+    fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            last: ['\0', '\0'],
+        }
+    }
+
+    #[inline]
+    fn ends_with(&self, second_to_last: char, last: char) -> bool {
+        self.last == [second_to_last, last]
+    }
+
+    #[inline]
+    fn ends_with_char(&self, last: char) -> bool {
+        self.last[1] == last
+    }
+}
+
+impl<W: Write + ?Sized> Write for TrailingChars<'_, W> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut chars = s.chars().rev();
+        match (chars.next(), chars.next()) {
+            (Some(last), Some(second_to_last)) => self.last = [second_to_last, last],
+            (Some(last), None) => self.last = [self.last[1], last],
+            _ => {}
+        }
+        self.inner.write_str(s)
+    }
+
+    #[inline]
+    fn write_char(&mut self, c: char) -> fmt::Result {
+        self.last = [self.last[1], c];
+        self.inner.write_char(c)
+    }
+}
+
+/// Writes `c` as a hexadecimal numeric character reference, e.g. `&#x1f600;`.
+#[inline]
+fn write_hex_reference<W: Write + ?Sized>(c: char, output: &mut W) -> fmt::Result {
+    output.write_str("&#x")?;
+    encode_as_hex_digits(output, c as u32)?;
+    output.write_char(';')
+}
+
+impl Encoder for XmlEncoder {
+    fn encode_into(&self, input: &str, output: &mut String) {
+        // Writing to a `String` never fails and invalid characters aren't reported.
+        let _ = self.write_encoded(input, output, false);
+    }
+
+    fn encode_fmt(&self, input: &str, output: &mut dyn Write) -> fmt::Result {
+        self.write_encoded(input, output, false)
+            .map_err(|_| fmt::Error)
+    }
+
+    fn first_unsafe_index(&self, input: &str) -> Option<usize> {
+        let (index, _) = input.char_indices().find(|&(index, c)| {
+            self.requires_encoding(c) && !self.is_harmless_in_context(input, index, c)
+        })?;
+        Some(self.context_run_start(input, index))
+    }
+
+    /// Holds back the last two `]` in [`XmlEncoderMode::CData`] and the last `-` in
+    /// [`XmlEncoderMode::Comment`], which decide how a following `>` or `-` is written. Invalid
+    /// characters that are replaced by a context character count as one; with
+    /// [`InvalidCharPolicy::Remove`] they are held back too, as they vanish from the output.
+    fn pending_suffix_len(&self, input: &str) -> usize {
+        let mut needed = match self.mode {
+            XmlEncoderMode::CData => 2,
+            XmlEncoderMode::Comment => 1,
+            _ => return 0,
+        };
+        let mut start = input.len();
+        for (index, c) in input.char_indices().rev() {
+            if needed == 0 {
+                break;
+            }
+            let written = match self.invalid_char_policy {
+                _ if !self.is_invalid(c) => Some(c),
+                InvalidCharPolicy::Remove => None,
+                InvalidCharPolicy::Replace(replacement) => Some(replacement),
+                InvalidCharPolicy::Error => Some(char::REPLACEMENT_CHARACTER),
+            };
+            match written {
+                Some(c) if self.is_context_char(c) => needed -= 1,
+                Some(_) => break,
+                None => {}
+            }
+            start = index;
+        }
+        input.len() - start
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::xml_encoder::{XmlEncoder, XmlEncoderMode};
+    use crate::encoder::Encoder;
+    use crate::xml_encoder::{
+        EntityStyle, InvalidCharPolicy, XmlEncoder, XmlEncoderMode, XmlVersion,
+    };
 
     fn generic_tests(encoder: &XmlEncoder) {
         assert_eq!("\u{fffd}", encoder.encode("\u{fffd}"));
         assert_eq!(" ", encoder.encode("\u{ffff}"));
+        assert_eq!(" ", encoder.encode("\u{fffe}"));
+        assert_eq!("\u{10000}", encoder.encode("\u{10000}"));
+        assert_eq!("a\u{1f600}b", encoder.encode("a\u{1f600}b"));
+        assert_eq!("\u{20000}", encoder.encode("\u{20000}"));
+        assert_eq!("\u{10ffff}", encoder.encode("\u{10ffff}"));
+        assert_eq!("\u{fdd0}\u{fdef}", encoder.encode("\u{fdd0}\u{fdef}"));
+        assert_eq!("\u{1fffe}\u{10fffe}", encoder.encode("\u{1fffe}\u{10fffe}"));
+    }
+
+    #[test]
+    fn test_strict_encode() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content).with_strict(true);
+        assert_eq!(
+            "\u{fdcf} \u{fdf0}",
+            encoder.encode("\u{fdcf}\u{fdd0}\u{fdf0}")
+        );
+        assert_eq!(" ", encoder.encode("\u{fdef}"));
+        assert_eq!(" ", encoder.encode("\u{fffe}"));
+        assert_eq!(" ", encoder.encode("\u{ffff}"));
+        assert_eq!("a b", encoder.encode("a\u{1fffe}b"));
+        assert_eq!("  ", encoder.encode("\u{2ffff}\u{10ffff}"));
+        assert_eq!(
+            "\u{fffd}\u{1f600}\u{1fffd}",
+            encoder.encode("\u{fffd}\u{1f600}\u{1fffd}")
+        );
+        assert_eq!("&lt;\u{20000}&gt;", encoder.encode("<\u{20000}>"));
     }
     #[test]
     fn test_all_encode() {
@@ -212,4 +619,327 @@ mod test {
 
         generic_tests(&encoder);
     }
+
+    #[test]
+    fn test_invalid_char_policy() {
+        let input = "a\u{1}b\u{ffff}c";
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content);
+        assert_eq!("a b c", encoder.encode(input));
+
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Remove);
+        assert_eq!("abc", encoder.encode(input));
+        assert_eq!("abc", encoder.try_encode(input).unwrap());
+
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('\u{fffd}'));
+        assert_eq!("a\u{fffd}b\u{fffd}c", encoder.encode(input));
+
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('?'));
+        assert_eq!("a?b?c", encoder.encode(input));
+
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('<'));
+        assert_eq!("a&lt;b&lt;c", encoder.encode(input));
+
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('\u{0}'));
+        assert_eq!("a\u{fffd}b\u{fffd}c", encoder.encode(input));
+    }
+
+    #[test]
+    fn test_invalid_char_error() {
+        let encoder =
+            XmlEncoder::new(XmlEncoderMode::All).with_invalid_char_policy(InvalidCharPolicy::Error);
+        assert_eq!(
+            "&lt;a&gt; \u{1f600}",
+            encoder.try_encode("<a> \u{1f600}").unwrap()
+        );
+
+        let error = encoder.try_encode("<a>\u{1f600}\u{1b}").unwrap_err();
+        assert_eq!(7, error.offset());
+        assert_eq!('\u{1b}', error.character());
+        assert_eq!(
+            "invalid XML character U+001B at byte offset 7",
+            error.to_string()
+        );
+
+        let error = encoder.try_encode("ab\u{ffff}").unwrap_err();
+        assert_eq!(2, error.offset());
+        assert_eq!('\u{ffff}', error.character());
+
+        // Infallible methods fall back to U+FFFD.
+        assert_eq!("&lt;\u{fffd}", encoder.encode("<\u{1b}"));
+        assert_eq!("&lt;\u{fffd}", encoder.display("<\u{1b}").to_string());
+    }
+
+    #[test]
+    fn test_ascii_only_encode() {
+        for mode in [
+            XmlEncoderMode::All,
+            XmlEncoderMode::Content,
+            XmlEncoderMode::Attribute,
+            XmlEncoderMode::SingleQuotedAttribute,
+            XmlEncoderMode::DoubleQuotedAttribute,
+        ] {
+            let encoder = XmlEncoder::new(mode).with_ascii_only(true);
+            assert_eq!("abc\u{7f}", encoder.encode("abc\u{7f}"));
+            assert_eq!("&#x80;", encoder.encode("\u{80}"));
+            assert_eq!("caf&#xe9;", encoder.encode("caf\u{e9}"));
+            assert_eq!("&#x4e2d;&#x6587;", encoder.encode("\u{4e2d}\u{6587}"));
+            assert_eq!("&#xfffd;", encoder.encode("\u{fffd}"));
+            assert_eq!("&#x10000;", encoder.encode("\u{10000}"));
+            assert_eq!("a&#x1f600;b", encoder.encode("a\u{1f600}b"));
+            assert_eq!("&#x10ffff;", encoder.encode("\u{10ffff}"));
+            assert_eq!("&amp;&lt;", encoder.encode("&<"));
+            assert_eq!(" ", encoder.encode("\u{ffff}"));
+            assert!(encoder.encode("\u{e9}\u{1f600}\u{2028}").is_ascii());
+        }
+    }
+
+    #[test]
+    fn test_ascii_only_invalid_char_policy() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content)
+            .with_ascii_only(true)
+            .with_invalid_char_policy(InvalidCharPolicy::Replace('\u{e9}'));
+        assert_eq!("a&#xe9;b", encoder.encode("a\u{1}b"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Error);
+        assert_eq!("a&#xfffd;b", encoder.encode("a\u{1}b"));
+    }
+
+    #[test]
+    fn test_cdata_encode() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData);
+        assert_eq!(
+            "<a href=\"x\">&amp;'</a>",
+            encoder.encode("<a href=\"x\">&amp;'</a>")
+        );
+        assert_eq!("]]]]><![CDATA[>", encoder.encode("]]>"));
+        assert_eq!(
+            "a]]]]><![CDATA[>b]]]]><![CDATA[>",
+            encoder.encode("a]]>b]]>")
+        );
+        assert_eq!("]]]]]><![CDATA[>", encoder.encode("]]]>"));
+        assert_eq!("] ]>]>>", encoder.encode("] ]>]>>"));
+        assert_eq!("]]", encoder.encode("]]"));
+        assert_eq!("\u{e9}\u{1f600}", encoder.encode("\u{e9}\u{1f600}"));
+        assert_eq!("a b", encoder.encode("a\u{1}b"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_cdata_invalid_chars_cannot_form_terminator() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData)
+            .with_invalid_char_policy(InvalidCharPolicy::Remove);
+        assert_eq!("]]]]><![CDATA[>", encoder.encode("]]\u{1}>"));
+        assert_eq!("]]]]><![CDATA[>", encoder.encode("]\u{1}]>"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('>'));
+        assert_eq!("]]]]><![CDATA[>", encoder.encode("]]\u{1}"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace(']'));
+        assert_eq!("]]]]><![CDATA[>", encoder.encode("\u{1}\u{1}>"));
+    }
+
+    #[test]
+    fn test_cdata_ascii_only() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData).with_ascii_only(true);
+        assert_eq!("caf]]>&#xe9;<![CDATA[", encoder.encode("caf\u{e9}"));
+        assert_eq!(
+            "]]>&#x1f600;<![CDATA[]]]]><![CDATA[>",
+            encoder.encode("\u{1f600}]]>")
+        );
+    }
+
+    #[test]
+    fn test_cdata_cow_and_streaming() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData);
+        assert!(!encoder.needs_encoding("a]>b]] >"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a]]>"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a]]]>"));
+        assert_eq!("a]]]]]><![CDATA[>", encoder.encode_cow("a]]]>"));
+        assert_eq!(2, encoder.pending_suffix_len("a]]"));
+        assert_eq!(0, encoder.pending_suffix_len("a]]>"));
+        assert_eq!(1, encoder.pending_suffix_len("a]\u{1}]"));
+        assert_eq!(2, encoder.pending_suffix_len("a]]]]"));
+        assert_eq!(
+            0,
+            XmlEncoder::new(XmlEncoderMode::Content).pending_suffix_len("a\u{1}")
+        );
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Remove);
+        assert_eq!(4, encoder.pending_suffix_len("a]]\u{1}]\u{1}"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace(']'));
+        assert_eq!(2, encoder.pending_suffix_len("a]\u{1}\u{1}"));
+    }
+
+    #[test]
+    fn test_comment_encode() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment);
+        assert_eq!(
+            "<a href=\"x\">&amp;'</a>",
+            encoder.encode("<a href=\"x\">&amp;'</a>")
+        );
+        assert_eq!("a-b-c", encoder.encode("a-b-c"));
+        assert_eq!("a- -b", encoder.encode("a--b"));
+        assert_eq!("a- - -b", encoder.encode("a---b"));
+        assert_eq!("- ->", encoder.encode("-->"));
+        assert_eq!("a- ", encoder.encode("a-"));
+        assert_eq!("- ", encoder.encode("-"));
+        assert_eq!("- - ", encoder.encode("--"));
+        assert_eq!("a b", encoder.encode("a\u{1}b"));
+        assert_eq!("\u{e9}\u{1f600}", encoder.encode("\u{e9}\u{1f600}"));
+        generic_tests(&encoder);
+    }
+
+    #[test]
+    fn test_comment_invalid_chars_cannot_form_double_hyphen() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment)
+            .with_invalid_char_policy(InvalidCharPolicy::Remove);
+        assert_eq!("a- -b", encoder.encode("a-\u{1}-b"));
+        assert_eq!("a- ", encoder.encode("a-\u{1}"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Replace('-'));
+        assert_eq!("a- -b", encoder.encode("a\u{1}\u{1}b"));
+        assert_eq!("a- ", encoder.encode("a\u{1}"));
+    }
+
+    #[test]
+    fn test_comment_ascii_only() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment).with_ascii_only(true);
+        assert_eq!(
+            "caf&#xe9; - &#x1f600;",
+            encoder.encode("caf\u{e9} - \u{1f600}")
+        );
+    }
+
+    #[test]
+    fn test_comment_cow_and_streaming() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment);
+        assert!(!encoder.needs_encoding("a-b -c"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a--b"));
+        assert_eq!(Some(1), encoder.first_unsafe_index("a-"));
+        assert_eq!("a- - -b", encoder.encode_cow("a---b"));
+        assert_eq!(1, encoder.pending_suffix_len("a-"));
+        assert_eq!(0, encoder.pending_suffix_len("a-b"));
+        assert_eq!(1, encoder.pending_suffix_len("a-\u{1}-"));
+        assert_eq!(1, encoder.pending_suffix_len("a---"));
+        let encoder = encoder.with_invalid_char_policy(InvalidCharPolicy::Remove);
+        assert_eq!(3, encoder.pending_suffix_len("a--\u{1}\u{1}"));
+    }
+
+    #[test]
+    fn test_attribute_whitespace_references() {
+        let input = "a\tb\nc\r\nd";
+        for mode in [
+            XmlEncoderMode::All,
+            XmlEncoderMode::Attribute,
+            XmlEncoderMode::SingleQuotedAttribute,
+            XmlEncoderMode::DoubleQuotedAttribute,
+        ] {
+            let encoder = XmlEncoder::new(mode);
+            assert_eq!(input, encoder.encode(input));
+            let encoder = encoder.with_attribute_whitespace_references(true);
+            assert_eq!("a&#9;b&#10;c&#13;&#10;d", encoder.encode(input));
+            assert_eq!("a b", encoder.encode("a\u{1}b"));
+            let encoder = encoder.with_attribute_whitespace_references(false);
+            assert_eq!(input, encoder.encode(input));
+        }
+        let encoder =
+            XmlEncoder::new(XmlEncoderMode::Content).with_attribute_whitespace_references(true);
+        assert_eq!(input, encoder.encode(input));
+    }
+
+    #[test]
+    fn test_entity_style() {
+        let modes: [(XmlEncoderMode, &str); 5] = [
+            (XmlEncoderMode::All, "&<>'\""),
+            (XmlEncoderMode::Content, "&<>"),
+            (XmlEncoderMode::Attribute, "&<'\""),
+            (XmlEncoderMode::SingleQuotedAttribute, "&<'"),
+            (XmlEncoderMode::DoubleQuotedAttribute, "&<\""),
+        ];
+        let styles: [(EntityStyle, [&str; 7]); 4] = [
+            (
+                EntityStyle::Mixed,
+                ["&amp;", "&lt;", "&gt;", "&#39;", "&#34;", "&#10;", "&#xe9;"],
+            ),
+            (
+                EntityStyle::Named,
+                [
+                    "&amp;", "&lt;", "&gt;", "&apos;", "&quot;", "&#xa;", "&#xe9;",
+                ],
+            ),
+            (
+                EntityStyle::Decimal,
+                [
+                    "&#38;", "&#60;", "&#62;", "&#39;", "&#34;", "&#10;", "&#233;",
+                ],
+            ),
+            (
+                EntityStyle::Hex,
+                [
+                    "&#x26;", "&#x3c;", "&#x3e;", "&#x27;", "&#x22;", "&#xa;", "&#xe9;",
+                ],
+            ),
+        ];
+        for (mode, encoded) in modes {
+            for (style, references) in styles {
+                let encoder = XmlEncoder::new(mode)
+                    .with_entity_style(style)
+                    .with_ascii_only(true)
+                    .with_attribute_whitespace_references(true);
+                for (c, reference) in ['&', '<', '>', '\'', '"', '\n', '\u{e9}']
+                    .into_iter()
+                    .zip(references)
+                {
+                    let expected = if encoded.contains(c)
+                        || c == '\u{e9}'
+                        || (c == '\n' && mode != XmlEncoderMode::Content)
+                    {
+                        reference.to_string()
+                    } else {
+                        c.to_string()
+                    };
+                    assert_eq!(
+                        expected,
+                        encoder.encode(&c.to_string()),
+                        "{mode:?} {style:?}"
+                    );
+                }
+                assert_eq!("a b", encoder.encode("a\u{1}b"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_entity_style_cdata_and_comment() {
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData)
+            .with_ascii_only(true)
+            .with_entity_style(EntityStyle::Decimal);
+        assert_eq!("<&]]>&#233;<![CDATA[", encoder.encode("<&\u{e9}"));
+        let encoder = XmlEncoder::new(XmlEncoderMode::Comment)
+            .with_ascii_only(true)
+            .with_entity_style(EntityStyle::Named);
+        assert_eq!("<&'&#xe9;", encoder.encode("<&'\u{e9}"));
+    }
+
+    #[test]
+    fn test_xml_1_1_encode() {
+        let input = "a\u{1}\u{1f}\t\n\r\u{7f}\u{85}\u{9f}\u{a0}\u{2028}\u{2029}b";
+        let encoder = XmlEncoder::new(XmlEncoderMode::Content);
+        assert_eq!(
+            "a  \t\n\r\u{7f}\u{85}\u{9f}\u{a0}\u{2028}\u{2029}b",
+            encoder.encode(input)
+        );
+
+        let encoder = encoder.with_version(XmlVersion::V1_1);
+        assert_eq!(
+            "a&#x1;&#x1f;\t\n\r&#x7f;&#x85;&#x9f;\u{a0}&#x2028;\u{2029}b",
+            encoder.encode(input)
+        );
+        assert_eq!("a b", encoder.encode("a\u{0}b"));
+        assert_eq!(" ", encoder.encode("\u{ffff}"));
+        assert_eq!("&lt;&#x1b;", encoder.try_encode("<\u{1b}").unwrap());
+        assert_eq!(Some(1), encoder.first_unsafe_index("a\u{85}"));
+        generic_tests(&encoder);
+
+        let encoder = encoder.with_entity_style(EntityStyle::Decimal);
+        assert_eq!("&#1;&#133;", encoder.encode("\u{1}\u{85}"));
+
+        let encoder = XmlEncoder::new(XmlEncoderMode::CData).with_version(XmlVersion::V1_1);
+        assert_eq!("a]]>&#x1;<![CDATA[b", encoder.encode("a\u{1}b"));
+    }
 }
@@ -0,0 +1,304 @@
+use crate::common::{char_bucket, char_mask};
+use std::borrow::Cow;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XmlNameMode {
+    /// An XML `Name`; colons are kept, so `prefix:local` passes through unchanged.
+    Name,
+    /// A namespace-safe `NCName`; colons are escaped like any other disallowed character.
+    NCName,
+}
+
+/// Turns arbitrary strings into valid XML names, e.g. for element names built from column
+/// headers.
+///
+/// Characters that are not allowed at their position are written as `_xHHHH_` (or
+/// `_xHHHHHHHH_` beyond the Basic Multilingual Plane) with uppercase hex digits, so `First Name`
+/// becomes `First_x0020_Name` and `1st` becomes `_x0031_st`. An underscore followed by `x` is
+/// escaped too (`_x005F_`), which keeps the scheme reversible with [`XmlNameEncoder::decode`].
+///
+/// Names are encoded as a whole, since whether a character is allowed depends on its position,
+/// so this type doesn't implement [`Encoder`](crate::encoder::Encoder). The empty string is
+/// encoded as `_x_`, an escape without digits that can't come from any other input, since an
+/// input `_x` is always escaped.
+/// What [`XmlNameEncoder::encode`] returns for the empty string.
+const EMPTY_NAME: &str = "_x_";
+
+pub struct XmlNameEncoder {
+    start_masks: [u32; 4],
+    name_masks: [u32; 4],
+}
+
+impl XmlNameEncoder {
+    pub fn new(mode: XmlNameMode) -> Self {
+        let mut start_masks = [0, 0, 0, 0];
+        for c in ('A'..='Z').chain('a'..='z').chain(['_', ':']) {
+            start_masks[char_bucket(c)] |= char_mask(c);
+        }
+        if mode == XmlNameMode::NCName {
+            start_masks[char_bucket(':')] &= !char_mask(':');
+        }
+        let mut name_masks = start_masks;
+        for c in ('0'..='9').chain(['-', '.']) {
+            name_masks[char_bucket(c)] |= char_mask(c);
+        }
+        Self {
+            start_masks,
+            name_masks,
+        }
+    }
+
+    pub fn encode(&self, input: &str) -> String {
+        self.encode_cow(input).into_owned()
+    }
+
+    /// Encodes `input`, borrowing it if it already is a valid name without `_x` sequences.
+    pub fn encode_cow<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        if input.is_empty() {
+            return Cow::Borrowed(EMPTY_NAME);
+        }
+        let Some(first) = input
+            .char_indices()
+            .position(|(index, c)| self.requires_encoding(input, index, c))
+        else {
+            return Cow::Borrowed(input);
+        };
+        let mut output = String::with_capacity(input.len() + 8);
+        let mut safe_start = 0;
+        for (index, c) in input.char_indices().skip(first) {
+            if !self.requires_encoding(input, index, c) {
+                continue;
+            }
+            output.push_str(&input[safe_start..index]);
+            safe_start = index + c.len_utf8();
+            // Writing to a `String` never fails.
+            let _ = if c as u32 > 0xFFFF {
+                write!(output, "_x{:08X}_", c as u32)
+            } else {
+                write!(output, "_x{:04X}_", c as u32)
+            };
+        }
+        output.push_str(&input[safe_start..]);
+        Cow::Owned(output)
+    }
+
+    /// Reverses [`XmlNameEncoder::encode`], for names produced in either mode.
+    ///
+    /// Every `_xHHHH_` or `_xHHHHHHHH_` sequence (in either case) that names a valid character is
+    /// replaced by that character; anything else is kept as-is. `_x_` on its own decodes to the
+    /// empty string.
+    pub fn decode<'a>(&self, input: &'a str) -> Cow<'a, str> {
+        if input == EMPTY_NAME {
+            return Cow::Borrowed("");
+        }
+        let mut output = String::new();
+        let mut copied = 0;
+        let mut search = 0;
+        while let Some(found) = input[search..].find("_x") {
+            let start = search + found;
+            match parse_escape(&input[start..]) {
+                Some((c, len)) => {
+                    output.push_str(&input[copied..start]);
+                    output.push(c);
+                    copied = start + len;
+                    search = copied;
+                }
+                None => search = start + 1,
+            }
+        }
+        if copied == 0 {
+            return Cow::Borrowed(input);
+        }
+        output.push_str(&input[copied..]);
+        Cow::Owned(output)
+    }
+
+    /// Returns `true` if `name` is a valid XML name in this mode, without escaping anything.
+    pub fn is_valid(&self, name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().is_some_and(|c| self.is_name_start_char(c))
+            && chars.all(|c| self.is_name_char(c))
+    }
+
+    /// Returns `true` if `c` at byte offset `index` of `input` has to be escaped.
+    #[inline]
+    fn requires_encoding(&self, input: &str, index: usize, c: char) -> bool {
+        if c == '_' {
+            input[index + 1..].starts_with('x')
+        } else if index == 0 {
+            !self.is_name_start_char(c)
+        } else {
+            !self.is_name_char(c)
+        }
+    }
+
+    #[inline]
+    fn is_name_start_char(&self, c: char) -> bool {
+        if (c as u32) < 128 {
+            self.start_masks[char_bucket(c)] & char_mask(c) != 0
+        } else {
+            is_non_ascii_name_start_char(c)
+        }
+    }
+
+    #[inline]
+    fn is_name_char(&self, c: char) -> bool {
+        if (c as u32) < 128 {
+            self.name_masks[char_bucket(c)] & char_mask(c) != 0
+        } else {
+            is_xml_name_char(c)
+        }
+    }
+}
+
+/// Returns `true` if `c` matches the XML 1.0 (Fifth Edition) `NameStartChar` production.
+#[inline]
+pub(crate) fn is_xml_name_start_char(c: char) -> bool {
+    match c {
+        'A'..='Z' | 'a'..='z' | '_' | ':' => true,
+        _ => !c.is_ascii() && is_non_ascii_name_start_char(c),
+    }
+}
+
+/// Returns `true` if `c` matches the XML 1.0 (Fifth Edition) `NameChar` production.
+#[inline]
+pub(crate) fn is_xml_name_char(c: char) -> bool {
+    is_xml_name_start_char(c)
+        || matches!(c,
+            '0'..='9' | '-' | '.' | '\u{b7}' | '\u{300}'..='\u{36f}' | '\u{203f}'..='\u{2040}')
+}
+
+/// The non-ASCII part of the XML 1.0 (Fifth Edition) `NameStartChar` production.
+#[inline]
+fn is_non_ascii_name_start_char(c: char) -> bool {
+    matches!(c,
+        '\u{c0}'..='\u{d6}'
+        | '\u{d8}'..='\u{f6}'
+        | '\u{f8}'..='\u{2ff}'
+        | '\u{370}'..='\u{37d}'
+        | '\u{37f}'..='\u{1fff}'
+        | '\u{200c}'..='\u{200d}'
+        | '\u{2070}'..='\u{218f}'
+        | '\u{2c00}'..='\u{2fef}'
+        | '\u{3001}'..='\u{d7ff}'
+        | '\u{f900}'..='\u{fdcf}'
+        | '\u{fdf0}'..='\u{fffd}'
+        | '\u{10000}'..='\u{effff}')
+}
+
+/// Parses an escape at the start of `input`, which starts with `_x`, and returns the escaped
+/// character and the length of the escape.
+fn parse_escape(input: &str) -> Option<(char, usize)> {
+    [4, 8].into_iter().find_map(|digits| {
+        let hex = input.get(2..2 + digits)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit())
+            || input.as_bytes().get(2 + digits) != Some(&b'_')
+        {
+            return None;
+        }
+        let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+        Some((c, digits + 3))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xml_name_encoder::{XmlNameEncoder, XmlNameMode};
+    use std::borrow::Cow;
+
+    fn round_trip_tests(encoder: &XmlNameEncoder) {
+        for input in [
+            "First Name",
+            "1st",
+            "_x0020_",
+            "__x",
+            "a:b",
+            "-a.b-",
+            "caf\u{e9} \u{1f600}",
+            "x_",
+            "\u{300}\u{b7}",
+            "\u{0}\u{ffff}\u{f0000}",
+            "",
+            "_x_",
+        ] {
+            assert_eq!(input, encoder.decode(&encoder.encode(input)), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn test_name_encode() {
+        let encoder = XmlNameEncoder::new(XmlNameMode::Name);
+        assert_eq!("First_x0020_Name", encoder.encode("First Name"));
+        assert_eq!("_x0031_st", encoder.encode("1st"));
+        assert_eq!("a1-b.c_d", encoder.encode("a1-b.c_d"));
+        assert_eq!("_x002D_a", encoder.encode("-a"));
+        assert_eq!("a:b", encoder.encode("a:b"));
+        assert_eq!(":a", encoder.encode(":a"));
+        assert_eq!("_x005F_x0020_", encoder.encode("_x0020_"));
+        assert_eq!("_x005F_x", encoder.encode("_x"));
+        assert_eq!("x_", encoder.encode("x_"));
+        assert_eq!("caf\u{e9}", encoder.encode("caf\u{e9}"));
+        assert_eq!("_x00B7_a\u{b7}", encoder.encode("\u{b7}a\u{b7}"));
+        assert_eq!(
+            "a\u{1f600}_x000F0000_",
+            encoder.encode("a\u{1f600}\u{f0000}")
+        );
+        assert_eq!("\u{10000}", encoder.encode("\u{10000}"));
+        assert_eq!("a_x003C__x0026_", encoder.encode("a<&"));
+        assert_eq!("_x_", encoder.encode(""));
+        assert!(encoder.is_valid(&encoder.encode("")));
+        assert_eq!("_x005F_x_", encoder.encode("_x_"));
+        round_trip_tests(&encoder);
+    }
+
+    #[test]
+    fn test_ncname_encode() {
+        let encoder = XmlNameEncoder::new(XmlNameMode::NCName);
+        assert_eq!("a_x003A_b", encoder.encode("a:b"));
+        assert_eq!("_x003A_a", encoder.encode(":a"));
+        assert_eq!("First_x0020_Name", encoder.encode("First Name"));
+        round_trip_tests(&encoder);
+    }
+
+    #[test]
+    fn test_decode() {
+        let encoder = XmlNameEncoder::new(XmlNameMode::Name);
+        assert_eq!("a b", encoder.decode("a_x0020_b"));
+        assert_eq!("a_b", encoder.decode("a_x005f_b"));
+        assert_eq!("\u{1f600}", encoder.decode("_x0001f600_"));
+        assert_eq!("_x00zz_", encoder.decode("_x00zz_"));
+        assert_eq!("_x0020", encoder.decode("_x0020"));
+        assert_eq!("_x0011FFFF_", encoder.decode("_x0011FFFF_"));
+        assert_eq!("_xD800_", encoder.decode("_xD800_"));
+        assert_eq!("_ ", encoder.decode("__x0020_"));
+        assert_eq!("", encoder.decode("_x_"));
+        assert_eq!("a_x_", encoder.decode("a_x_"));
+    }
+
+    #[test]
+    fn test_is_valid() {
+        let name = XmlNameEncoder::new(XmlNameMode::Name);
+        let ncname = XmlNameEncoder::new(XmlNameMode::NCName);
+        for valid in ["a", "_x0020_", "caf\u{e9}", "a-1.b", "\u{10000}"] {
+            assert!(name.is_valid(valid) && ncname.is_valid(valid), "{valid}");
+        }
+        for invalid in ["", "1a", "-a", "a b", "a<", "\u{b7}"] {
+            assert!(
+                !name.is_valid(invalid) && !ncname.is_valid(invalid),
+                "{invalid}"
+            );
+        }
+        assert!(name.is_valid("svg:rect"));
+        assert!(!ncname.is_valid("svg:rect"));
+    }
+
+    #[test]
+    fn test_cow() {
+        let encoder = XmlNameEncoder::new(XmlNameMode::NCName);
+        assert!(matches!(encoder.encode_cow("column_1"), Cow::Borrowed(_)));
+        assert!(matches!(encoder.encode_cow("column 1"), Cow::Owned(_)));
+        assert!(matches!(encoder.decode("column_1"), Cow::Borrowed(_)));
+        assert!(matches!(encoder.decode("column_x0020_1"), Cow::Owned(_)));
+    }
+}
@@ -0,0 +1,377 @@
+use crate::encoder::Encoder;
+use crate::xml_encoder::{XmlEncoder, XmlEncoderMode};
+use crate::xml_name_encoder::{XmlNameEncoder, XmlNameMode};
+use std::fmt::{self, Write};
+use std::io;
+
+/// Returned by the [`XmlWriter`] methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XmlWriteError {
+    /// The element or attribute name is not a valid XML name.
+    InvalidName(String),
+    /// [`XmlWriter::attribute`] was called after the start tag was closed by content.
+    AttributeOutsideStartTag,
+    /// [`XmlWriter::attribute`] was called twice with the same name for one start tag.
+    DuplicateAttribute(String),
+    /// Content was written, or an element ended, while no element was open.
+    NoOpenElement,
+    /// [`XmlWriter::finish`] was called while the named element was still open.
+    UnclosedElement(String),
+    /// The underlying writer failed. For an [`IoWriter`], the `io::Error` is kept by the adapter.
+    Fmt,
+}
+
+impl From<fmt::Error> for XmlWriteError {
+    fn from(_: fmt::Error) -> Self {
+        XmlWriteError::Fmt
+    }
+}
+
+impl fmt::Display for XmlWriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlWriteError::InvalidName(name) => write!(f, "invalid XML name {:?}", name),
+            XmlWriteError::AttributeOutsideStartTag => {
+                f.write_str("attribute written outside of a start tag")
+            }
+            XmlWriteError::DuplicateAttribute(name) => {
+                write!(f, "attribute {:?} was already written", name)
+            }
+            XmlWriteError::NoOpenElement => f.write_str("no element is open"),
+            XmlWriteError::UnclosedElement(name) => write!(f, "element {:?} was not closed", name),
+            XmlWriteError::Fmt => f.write_str("the underlying writer failed"),
+        }
+    }
+}
+
+impl std::error::Error for XmlWriteError {}
+
+/// Writes well-formed XML, picking the right [`XmlEncoderMode`] for every position.
+///
+/// Text goes through [`XmlEncoderMode::Content`], attribute values through
+/// [`XmlEncoderMode::DoubleQuotedAttribute`] with whitespace written as references, and CDATA
+/// sections and comments through [`XmlEncoderMode::CData`] and [`XmlEncoderMode::Comment`].
+/// Element and attribute names are checked against the XML `Name` production, and open elements
+/// are tracked so every end tag matches its start tag. Several top-level elements may be written,
+/// so the writer can also produce fragments.
+///
+/// After an error the output may contain a partial construct and should be discarded.
+///
+/// # Example
+/// ```
+/// use tiny_clean::xml_writer::XmlWriter;
+///
+/// let mut writer = XmlWriter::new(String::new());
+/// writer.start_element("note").unwrap();
+/// writer.attribute("title", "\"Q&A\"").unwrap();
+/// writer.text("1 < 2").unwrap();
+/// writer.start_element("br").unwrap();
+/// writer.end_element().unwrap();
+/// writer.end_element().unwrap();
+/// assert_eq!(
+///     "<note title=\"&#34;Q&amp;A&#34;\">1 &lt; 2<br/></note>",
+///     writer.finish().unwrap()
+/// );
+/// ```
+pub struct XmlWriter<W: Write> {
+    output: W,
+    /// Names of the open elements, innermost last.
+    open_elements: Vec<String>,
+    /// Whether the innermost start tag still lacks its closing `>`.
+    start_tag_open: bool,
+    /// Names of the attributes written to the open start tag.
+    attribute_names: Vec<String>,
+    names: XmlNameEncoder,
+    content: XmlEncoder,
+    attribute: XmlEncoder,
+    cdata: XmlEncoder,
+    comment: XmlEncoder,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            open_elements: Vec::new(),
+            start_tag_open: false,
+            attribute_names: Vec::new(),
+            names: XmlNameEncoder::new(XmlNameMode::Name),
+            content: XmlEncoder::new(XmlEncoderMode::Content),
+            attribute: XmlEncoder::new(XmlEncoderMode::DoubleQuotedAttribute)
+                .with_attribute_whitespace_references(true),
+            cdata: XmlEncoder::new(XmlEncoderMode::CData),
+            comment: XmlEncoder::new(XmlEncoderMode::Comment),
+        }
+    }
+
+    /// Returns a reference to the wrapped writer.
+    pub fn get_ref(&self) -> &W {
+        &self.output
+    }
+
+    /// Returns a mutable reference to the wrapped writer.
+    ///
+    /// Writing to it directly bypasses the encoders and can produce malformed XML.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    /// Writes the start tag of an element. It stays open for attributes until content or an end
+    /// tag is written.
+    pub fn start_element(&mut self, name: &str) -> Result<(), XmlWriteError> {
+        self.check_name(name)?;
+        self.close_start_tag()?;
+        self.output.write_char('<')?;
+        self.output.write_str(name)?;
+        self.open_elements.push(name.to_string());
+        self.start_tag_open = true;
+        self.attribute_names.clear();
+        Ok(())
+    }
+
+    /// Adds an attribute to the start tag that was just written. Each name may only be used once
+    /// per start tag.
+    pub fn attribute(&mut self, name: &str, value: &str) -> Result<(), XmlWriteError> {
+        if !self.start_tag_open {
+            return Err(XmlWriteError::AttributeOutsideStartTag);
+        }
+        self.check_name(name)?;
+        if self.attribute_names.iter().any(|written| written == name) {
+            return Err(XmlWriteError::DuplicateAttribute(name.to_string()));
+        }
+        self.attribute_names.push(name.to_string());
+        self.output.write_char(' ')?;
+        self.output.write_str(name)?;
+        self.output.write_str("=\"")?;
+        self.attribute.encode_fmt(value, &mut self.output)?;
+        self.output.write_char('"')?;
+        Ok(())
+    }
+
+    /// Writes character data inside the current element.
+    pub fn text(&mut self, text: &str) -> Result<(), XmlWriteError> {
+        self.start_content()?;
+        self.content.encode_fmt(text, &mut self.output)?;
+        Ok(())
+    }
+
+    /// Writes a CDATA section inside the current element.
+    pub fn cdata(&mut self, text: &str) -> Result<(), XmlWriteError> {
+        self.start_content()?;
+        self.output.write_str("<![CDATA[")?;
+        self.cdata.encode_fmt(text, &mut self.output)?;
+        self.output.write_str("]]>")?;
+        Ok(())
+    }
+
+    /// Writes a comment, either inside the current element or at the top level.
+    pub fn comment(&mut self, text: &str) -> Result<(), XmlWriteError> {
+        self.close_start_tag()?;
+        self.output.write_str("<!--")?;
+        self.comment.encode_fmt(text, &mut self.output)?;
+        self.output.write_str("-->")?;
+        Ok(())
+    }
+
+    /// Ends the innermost open element, as `<name/>` if nothing was written inside it.
+    pub fn end_element(&mut self) -> Result<(), XmlWriteError> {
+        let name = self
+            .open_elements
+            .pop()
+            .ok_or(XmlWriteError::NoOpenElement)?;
+        if self.start_tag_open {
+            self.start_tag_open = false;
+            self.output.write_str("/>")?;
+        } else {
+            self.output.write_str("</")?;
+            self.output.write_str(&name)?;
+            self.output.write_char('>')?;
+        }
+        Ok(())
+    }
+
+    /// Returns the wrapped writer, failing if an element is still open.
+    pub fn finish(self) -> Result<W, XmlWriteError> {
+        match self.open_elements.last() {
+            Some(name) => Err(XmlWriteError::UnclosedElement(name.clone())),
+            None => Ok(self.output),
+        }
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), XmlWriteError> {
+        if self.names.is_valid(name) {
+            Ok(())
+        } else {
+            Err(XmlWriteError::InvalidName(name.to_string()))
+        }
+    }
+
+    /// Closes the start tag, if any, and checks that content can be written.
+    fn start_content(&mut self) -> Result<(), XmlWriteError> {
+        if self.open_elements.is_empty() {
+            return Err(XmlWriteError::NoOpenElement);
+        }
+        self.close_start_tag()
+    }
+
+    fn close_start_tag(&mut self) -> Result<(), XmlWriteError> {
+        if self.start_tag_open {
+            self.start_tag_open = false;
+            self.output.write_char('>')?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: io::Write> XmlWriter<IoWriter<W>> {
+    /// Creates a writer that writes to an [`io::Write`] implementation.
+    ///
+    /// If writing fails with [`XmlWriteError::Fmt`], the `io::Error` can be taken from the
+    /// adapter with `get_mut().take_error()`.
+    pub fn from_io(inner: W) -> Self {
+        Self::new(IoWriter::new(inner))
+    }
+}
+
+/// Adapts an [`io::Write`] implementation to [`fmt::Write`] for use with [`XmlWriter`], keeping
+/// the first `io::Error` since `fmt::Error` can't carry it.
+pub struct IoWriter<W: io::Write> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> IoWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
+
+    /// Returns the error that made the last write fail, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Returns the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: io::Write> Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::xml_writer::{XmlWriteError, XmlWriter};
+    use std::io;
+
+    #[test]
+    fn test_document() {
+        let mut writer = XmlWriter::new(String::new());
+        writer.comment("generated -- do not edit-").unwrap();
+        writer.start_element("svg:g").unwrap();
+        writer.attribute("id", "a'b\"c\n<&>").unwrap();
+        writer.attribute("xmlns:svg", "urn:x").unwrap();
+        writer.text("x > y && ]]>").unwrap();
+        writer.cdata("if (a]]>b) {}").unwrap();
+        writer.start_element("empty").unwrap();
+        writer.end_element().unwrap();
+        writer.comment("note").unwrap();
+        writer.end_element().unwrap();
+        assert_eq!(
+            "<!--generated - - do not edit- -->\
+             <svg:g id=\"a'b&#34;c&#10;&lt;&amp;>\" xmlns:svg=\"urn:x\">\
+             x &gt; y &amp;&amp; ]]&gt;\
+             <![CDATA[if (a]]]]><![CDATA[>b) {}]]>\
+             <empty/><!--note--></svg:g>",
+            writer.finish().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let mut writer = XmlWriter::new(String::new());
+        assert_eq!(
+            Err(XmlWriteError::InvalidName("1a".to_string())),
+            writer.start_element("1a")
+        );
+        assert!(writer.start_element("").is_err());
+        writer.start_element("a").unwrap();
+        assert_eq!(
+            Err(XmlWriteError::InvalidName("on click".to_string())),
+            writer.attribute("on click", "x")
+        );
+        assert!(writer.attribute("a\"b", "x").is_err());
+        writer.end_element().unwrap();
+        assert_eq!("<a/>", writer.finish().unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_attributes() {
+        let mut writer = XmlWriter::new(String::new());
+        writer.start_element("a").unwrap();
+        writer.attribute("x", "1").unwrap();
+        assert_eq!(
+            Err(XmlWriteError::DuplicateAttribute("x".to_string())),
+            writer.attribute("x", "2")
+        );
+        writer.attribute("X", "3").unwrap();
+        writer.start_element("b").unwrap();
+        writer.attribute("x", "4").unwrap();
+        writer.end_element().unwrap();
+        writer.end_element().unwrap();
+        assert_eq!(
+            "<a x=\"1\" X=\"3\"><b x=\"4\"/></a>",
+            writer.finish().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_nesting_errors() {
+        let mut writer = XmlWriter::new(String::new());
+        assert_eq!(Err(XmlWriteError::NoOpenElement), writer.text("x"));
+        assert_eq!(Err(XmlWriteError::NoOpenElement), writer.cdata("x"));
+        assert_eq!(Err(XmlWriteError::NoOpenElement), writer.end_element());
+        assert_eq!(
+            Err(XmlWriteError::AttributeOutsideStartTag),
+            writer.attribute("a", "b")
+        );
+        writer.start_element("a").unwrap();
+        writer.text("x").unwrap();
+        assert_eq!(
+            Err(XmlWriteError::AttributeOutsideStartTag),
+            writer.attribute("b", "c")
+        );
+        writer.start_element("b").unwrap();
+        writer.end_element().unwrap();
+        assert_eq!(
+            Err(XmlWriteError::UnclosedElement("a".to_string())),
+            writer.finish().map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_io_writer() {
+        let mut writer = XmlWriter::from_io(Vec::new());
+        writer.start_element("a").unwrap();
+        writer.text("caf\u{e9} & tea").unwrap();
+        writer.end_element().unwrap();
+        let output = writer.finish().unwrap().into_inner();
+        assert_eq!(b"<a>caf\xC3\xA9 &amp; tea</a>", &output[..]);
+    }
+
+    #[test]
+    fn test_io_error() {
+        let mut buffer = [0u8; 4];
+        let mut writer = XmlWriter::from_io(&mut buffer[..]);
+        writer.start_element("abc").unwrap();
+        assert_eq!(Err(XmlWriteError::Fmt), writer.text("long text"));
+        let error = writer.get_mut().take_error().unwrap();
+        assert_eq!(io::ErrorKind::WriteZero, error.kind());
+    }
+}